@@ -0,0 +1,8 @@
+-- Initial admin. The hash is random, so no password matches it: the real
+-- one is set at deploy time through `APP_APPLICATION__ADMIN_PASSWORD_HASH`.
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+    'ddf8994f-d522-4659-8d02-c1d479057be6',
+    'admin',
+    '$argon2id$v=19$m=15000,t=2,p=1$i0CmSj0BCu7bIdgVhiesrg$hYKIJFaU0qI/REUPfhhcN+GNwXyQSo+X8HoeyUMy+0s'
+);
@@ -0,0 +1,9 @@
+BEGIN;
+ALTER TABLE subscription_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours';
+ALTER TABLE subscription_tokens
+ALTER COLUMN created_at DROP DEFAULT,
+ALTER COLUMN expires_at DROP DEFAULT;
+CREATE INDEX subscription_tokens_subscriber_id_idx ON subscription_tokens (subscriber_id);
+COMMIT;
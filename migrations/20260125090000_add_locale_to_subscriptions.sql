@@ -0,0 +1,3 @@
+-- NULL for subscribers who joined before emails were localized: they get the
+-- default locale from `application.default_locale`.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NULL;
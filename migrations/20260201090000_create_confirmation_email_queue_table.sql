@@ -0,0 +1,12 @@
+-- Confirmation emails go out from the delivery worker, not while handling
+-- the request: how long `/subscriptions` takes must not tell whether the
+-- address was already on the list. Deleting a token drops its email.
+CREATE TABLE confirmation_email_queue(
+    subscription_token TEXT NOT NULL
+        REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+    subscriber_name TEXT NOT NULL,
+    locale TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscription_token)
+);
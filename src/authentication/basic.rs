@@ -0,0 +1,187 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{
+    dev::Payload,
+    http::{
+        header::{self, HeaderMap, HeaderValue},
+        StatusCode,
+    },
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use base64::Engine;
+use secrecy::Secret;
+use sqlx::PgPool;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    routes::error_chain_fmt,
+};
+
+const REALM: &str = r#"Basic realm="publish""#;
+
+/// A caller authenticated through the `Authorization: Basic` header.
+/// Meant for machine clients (e.g. CI) that can't go through the login form.
+#[derive(Debug)]
+pub struct BasicAuthUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum BasicAuthError {
+    #[error("The 'Authorization' header was missing.")]
+    MissingHeader,
+    #[error("The 'Authorization' header is not a valid 'Basic' header: {0}.")]
+    InvalidHeader(&'static str),
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] AuthError),
+    #[error("Failed to validate the credentials.")]
+    UnexpectedError(#[source] AuthError),
+    #[error("No database pool is registered in the application data.")]
+    MissingPool,
+}
+
+impl std::fmt::Debug for BasicAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<AuthError> for BasicAuthError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => BasicAuthError::InvalidCredentials(e),
+            _ => BasicAuthError::UnexpectedError(e),
+        }
+    }
+}
+
+impl ResponseError for BasicAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BasicAuthError::MissingHeader
+            | BasicAuthError::InvalidHeader(_)
+            | BasicAuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+            BasicAuthError::UnexpectedError(_) | BasicAuthError::MissingPool => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(REALM));
+        }
+        response
+    }
+}
+
+impl FromRequest for BasicAuthUser {
+    type Error = BasicAuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let credentials = basic_authentication(req.headers());
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        // Only there when the app is wrapped in `TracingLogger<AppRootSpanBuilder>`.
+        let root_span = req.extensions().get::<RootSpan>().cloned();
+
+        Box::pin(async move {
+            let credentials = credentials?;
+            let pool = pool.ok_or(BasicAuthError::MissingPool)?;
+            if let Some(span) = &root_span {
+                span.record("username", tracing::field::display(&credentials.username));
+            }
+
+            let username = credentials.username.clone();
+            let user_id = validate_credentials(credentials, &pool).await?;
+            if let Some(span) = &root_span {
+                span.record("user_id", tracing::field::display(&user_id));
+            }
+
+            Ok(BasicAuthUser { user_id, username })
+        })
+    }
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, BasicAuthError> {
+    let header_value = headers
+        .get(header::AUTHORIZATION)
+        .ok_or(BasicAuthError::MissingHeader)?
+        .to_str()
+        .map_err(|_| BasicAuthError::InvalidHeader("not a valid UTF8 string"))?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or(BasicAuthError::InvalidHeader("the scheme is not 'Basic'"))?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| BasicAuthError::InvalidHeader("the credentials are not base64-encoded"))?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| BasicAuthError::InvalidHeader("the credentials are not valid UTF8"))?;
+
+    let (username, password) =
+        decoded_credentials
+            .split_once(':')
+            .ok_or(BasicAuthError::InvalidHeader(
+                "no ':' separating username and password",
+            ))?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use secrecy::ExposeSecret;
+
+    use super::{basic_authentication, BasicAuthError};
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn a_well_formed_header_is_parsed() {
+        // base64("ci-bot:pass:word")
+        let credentials = basic_authentication(&headers("Basic Y2ktYm90OnBhc3M6d29yZA==")).unwrap();
+
+        assert_eq!(credentials.username, "ci-bot");
+        assert_eq!(credentials.password.expose_secret(), "pass:word");
+    }
+
+    #[test]
+    fn a_missing_header_is_rejected() {
+        let outcome = basic_authentication(&HeaderMap::new());
+
+        assert!(matches!(outcome, Err(BasicAuthError::MissingHeader)));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let cases = [
+            ("Bearer Y2ktYm90OnBhc3N3b3Jk", "wrong scheme"),
+            ("Basic not-base64!", "invalid base64"),
+            ("Basic Y2ktYm90", "no separator"),
+        ];
+
+        for (value, description) in cases {
+            let outcome = basic_authentication(&headers(value));
+
+            assert!(
+                matches!(outcome, Err(BasicAuthError::InvalidHeader(_))),
+                "The header was accepted despite: {}.",
+                description
+            );
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::ops::Deref;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    middleware::Next,
+    FromRequest, HttpMessage,
+};
+use uuid::Uuid;
+
+use crate::{
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+/// Id of the logged-in user, made available to the handlers behind
+/// [`reject_anonymous_users`] via `web::ReqData<UserId>`.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    match session.get_user_id().map_err(e500)? {
+        Some(user_id) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        }
+        None => {
+            let response = see_other("/login");
+            Err(InternalError::from_response("The user has not logged in.", response).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_session::SessionMiddleware;
+    use actix_web::{
+        cookie::Key,
+        http::{header::LOCATION, StatusCode},
+        middleware::from_fn,
+        test, web, App, HttpResponse,
+    };
+    use uuid::Uuid;
+
+    use super::{reject_anonymous_users, UserId};
+    use crate::{session_state::TypedSession, session_store::InMemorySessionStore};
+
+    async fn log_in(session: TypedSession) -> HttpResponse {
+        session
+            .insert_user_id(Uuid::nil())
+            .expect("Failed to insert the user id");
+        HttpResponse::Ok().finish()
+    }
+
+    async fn whoami(user_id: web::ReqData<UserId>) -> HttpResponse {
+        HttpResponse::Ok().body(user_id.into_inner().to_string())
+    }
+
+    macro_rules! app {
+        ($store:expr) => {
+            test::init_service(
+                App::new()
+                    .wrap(SessionMiddleware::new($store, Key::generate()))
+                    .route("/login", web::post().to(log_in))
+                    .service(
+                        web::scope("/admin")
+                            .wrap(from_fn(reject_anonymous_users))
+                            .route("/whoami", web::get().to(whoami)),
+                    ),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn anonymous_users_are_redirected_to_the_login_form() {
+        let app = app!(InMemorySessionStore::new());
+
+        let request = test::TestRequest::get().uri("/admin/whoami").to_request();
+        let response = test::try_call_service(&app, request)
+            .await
+            .expect_err("Anonymous users should be rejected")
+            .error_response();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "/login");
+    }
+
+    #[actix_web::test]
+    async fn logged_in_users_get_their_id_injected() {
+        let app = app!(InMemorySessionStore::new());
+
+        let request = test::TestRequest::post().uri("/login").to_request();
+        let response = test::call_service(&app, request).await;
+        let cookie = response
+            .response()
+            .cookies()
+            .next()
+            .expect("No session cookie was set")
+            .into_owned();
+
+        let request = test::TestRequest::get()
+            .uri("/admin/whoami")
+            .cookie(cookie)
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+
+        assert_eq!(body, Uuid::nil().to_string());
+    }
+}
@@ -0,0 +1,9 @@
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::{BasicAuthError, BasicAuthUser};
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{
+    change_password, set_admin_password_hash, validate_credentials, AuthError, Credentials,
+};
@@ -0,0 +1,228 @@
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::telemetry::spawn_blocking_with_tracing;
+
+/// Verified against when the username does not exist, so that unknown
+/// usernames take as long to reject as wrong passwords.
+/// Same Argon2id parameters as the hashes we store.
+const FALLBACK_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    8OZwmpDjH3PIeDJ7zzwylQ$4uXFIcim5OTh+Jf2Xg2SkSWkDQYpSa7D3tMLEmvdMkY";
+
+/// Hashes the `admin` user was seeded with: the one of the current seed
+/// migration matches no password, the earlier one had its password
+/// published. Either means the admin never picked a password of their own.
+const SEEDED_ADMIN_PASSWORD_HASHES: [&str; 2] = [
+    "$argon2id$v=19$m=15000,t=2,p=1$\
+    i0CmSj0BCu7bIdgVhiesrg$hYKIJFaU0qI/REUPfhhcN+GNwXyQSo+X8HoeyUMy+0s",
+    "$argon2id$v=19$m=15000,t=2,p=1$\
+    Xt/hAPmpPW8PO6MgP/+7gw$fZE4jMv561TedZ9U48h3NV2K8E+Hw7ihF4FyFlLGd5I",
+];
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] argon2::password_hash::Error),
+    #[error("Failed to retrieve the stored credentials.")]
+    GetStoredCredentialsError(#[source] sqlx::Error),
+    #[error("Failed to parse the stored password hash in PHC string format.")]
+    InvalidStoredHash(#[source] argon2::password_hash::Error),
+    #[error("Failed to spawn the blocking task hashing or verifying the password.")]
+    SpawnBlockingError(#[source] tokio::task::JoinError),
+    #[error("Failed to hash the new password.")]
+    HashPasswordError(#[source] argon2::password_hash::Error),
+    #[error("Failed to store the new password hash.")]
+    UpdatePasswordError(#[source] sqlx::Error),
+    #[error("The new password hash is not a valid PHC string.")]
+    InvalidNewHash(#[source] argon2::password_hash::Error),
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(FALLBACK_PASSWORD_HASH.to_string());
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(AuthError::GetStoredCredentialsError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    // Argon2 is CPU-bound on purpose: keep it off the async executor.
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .map_err(AuthError::SpawnBlockingError)??;
+
+    user_id.ok_or(AuthError::InvalidCredentials(
+        argon2::password_hash::Error::Password,
+    ))
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map_err(AuthError::InvalidStoredHash)?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), AuthError> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .map_err(AuthError::SpawnBlockingError)??;
+
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(AuthError::UpdatePasswordError)?;
+    Ok(())
+}
+
+/// Gives the seeded `admin` user its first password, from a hash computed
+/// outside of the application so the password itself never reaches it.
+///
+/// A one-shot bootstrap: once the admin has a password of their own, set
+/// through `/admin/password`, the configured hash is ignored.
+#[tracing::instrument(name = "Bootstrap the admin password hash", skip_all)]
+pub async fn set_admin_password_hash(
+    password_hash: &Secret<String>,
+    pool: &PgPool,
+) -> Result<(), AuthError> {
+    // A typo would lock the admin out at the next login: fail on startup instead.
+    PasswordHash::new(password_hash.expose_secret()).map_err(AuthError::InvalidNewHash)?;
+
+    let seeded_hashes: Vec<String> = SEEDED_ADMIN_PASSWORD_HASHES
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let result = sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE username = 'admin' AND password_hash = ANY($2)
+        "#,
+        password_hash.expose_secret(),
+        &seeded_hashes,
+    )
+    .execute(pool)
+    .await
+    .map_err(AuthError::UpdatePasswordError)?;
+    if result.rows_affected() > 0 {
+        tracing::info!("Set the password of the seeded admin user");
+    }
+    Ok(())
+}
+
+fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, AuthError> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).expect("Valid Argon2 parameters"),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(AuthError::HashPasswordError)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+            SELECT user_id, password_hash
+            FROM users
+            WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::PasswordHash;
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::{
+        compute_password_hash, verify_password_hash, FALLBACK_PASSWORD_HASH,
+        SEEDED_ADMIN_PASSWORD_HASHES,
+    };
+
+    #[test]
+    fn the_fallback_hash_uses_the_same_parameters_as_stored_hashes() {
+        let hash = PasswordHash::new(FALLBACK_PASSWORD_HASH).unwrap();
+
+        assert_eq!(hash.algorithm.as_str(), "argon2id");
+        assert_eq!(hash.params.get_decimal("m"), Some(15000));
+        assert_eq!(hash.params.get_decimal("t"), Some(2));
+        assert_eq!(hash.params.get_decimal("p"), Some(1));
+    }
+
+    #[test]
+    fn computed_hashes_verify_against_their_password() {
+        let password = "a-brand-new-password";
+
+        let hash = compute_password_hash(Secret::new(password.into())).unwrap();
+        let parsed = PasswordHash::new(hash.expose_secret()).unwrap();
+
+        assert_eq!(parsed.params.get_decimal("m"), Some(15000));
+        assert!(verify_password_hash(hash.clone(), Secret::new(password.into())).is_ok());
+        assert!(verify_password_hash(hash, Secret::new("another-password".into())).is_err());
+    }
+
+    #[test]
+    fn the_seed_migration_uses_a_known_placeholder_hash() {
+        let migration = include_str!("../../migrations/20251228100500_seed_user.sql");
+
+        assert!(migration.contains(SEEDED_ADMIN_PASSWORD_HASHES[0]));
+    }
+}
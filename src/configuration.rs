@@ -1,7 +1,15 @@
+use std::sync::Arc;
+
 use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{
+        EmailClient, EmailSender, EmailTemplates, FileSinkEmailSender, RetryPolicy,
+        SmtpEmailSender, TemplateError,
+    },
+};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Enviroment {
@@ -33,15 +41,107 @@ impl TryFrom<String> for Enviroment {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    Postmark,
+    Smtp,
+    File,
+}
+
 #[derive(Clone, serde::Deserialize)]
 pub struct EmailClientSettings {
+    pub backend: EmailBackend,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
+    /// Folder holding the subject, HTML and plain-text templates of every
+    /// email, read once at startup.
+    pub templates_directory: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub require_tls: bool,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct FileSinkSettings {
+    pub directory: String,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailRetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+        }
+    }
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        let timeout = self.timeout();
+        match self.backend {
+            EmailBackend::Postmark => Arc::new(EmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                self.retry.policy(),
+            )),
+            EmailBackend::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("Missing `email_client.smtp` settings for the smtp backend");
+                let credentials = smtp.username.zip(smtp.password);
+                Arc::new(
+                    SmtpEmailSender::new(
+                        &smtp.host,
+                        smtp.port,
+                        credentials,
+                        smtp.require_tls,
+                        sender_email,
+                        timeout,
+                    )
+                    .expect("Failed to build the SMTP transport"),
+                )
+            }
+            EmailBackend::File => {
+                let file_sink = self
+                    .file_sink
+                    .expect("Missing `email_client.file_sink` settings for the file backend");
+                Arc::new(FileSinkEmailSender::new(
+                    file_sink.directory.into(),
+                    sender_email,
+                ))
+            }
+        }
+    }
+
+    pub fn templates(&self, default_locale: &str) -> Result<EmailTemplates, TemplateError> {
+        EmailTemplates::load(&self.templates_directory, default_locale)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -56,6 +156,68 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub telemetry: TelemetrySettings,
+    pub health: HealthSettings,
+}
+
+/// Readiness probe, see `routes::health_ready`.
+#[derive(Clone, serde::Deserialize)]
+pub struct HealthSettings {
+    /// Also check that the email API answers. Only meaningful with the
+    /// Postmark backend.
+    pub check_email_api: bool,
+    pub timeout_milliseconds: u64,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct TelemetrySettings {
+    pub otlp: OtlpSettings,
+}
+
+/// Where to send traces, see `telemetry::init_tracer_provider`.
+#[derive(Clone, serde::Deserialize)]
+pub struct OtlpSettings {
+    pub enabled: bool,
+    /// OTLP/HTTP traces endpoint of the collector, path included.
+    pub endpoint: String,
+    pub timeout_milliseconds: u64,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link stays valid.
+    pub token_ttl_hours: i64,
+    /// Minimum delay between two confirmation emails to the same address.
+    pub resend_cooldown_seconds: i64,
+    /// Pending subscribers are deleted once their last confirmation link
+    /// has been expired for this long.
+    pub pending_retention_hours: i64,
+    pub cleanup_interval_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.token_ttl_hours)
+    }
+
+    pub fn resend_cooldown(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.resend_cooldown_seconds)
+    }
+
+    pub fn pending_retention(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.pending_retention_hours)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -73,8 +235,33 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Signs the session and flash message cookies. At least 64 bytes long.
+    ///
+    /// Only `local.yml` has one: elsewhere it comes from
+    /// `APP_APPLICATION__HMAC_SECRET`, and the configuration fails to load
+    /// without it.
+    pub hmac_secret: Secret<String>,
+    /// Argon2 hash, in PHC string format, set as the password of the `admin`
+    /// user on startup while it still has the seeded one, which matches no
+    /// password. Later changes go through `/admin/password`.
+    pub admin_password_hash: Option<Secret<String>>,
+    /// Locale of the emails sent to subscribers who did not pick one we
+    /// have templates for.
+    pub default_locale: String,
+    /// How long in-flight requests and email sends get to finish once a
+    /// shutdown is requested.
+    pub shutdown_grace_period_seconds: u64,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_grace_period_seconds)
+    }
 }
 
+/// Layers, from lowest to highest precedence: `base.yml`, the file of the
+/// environment, and `APP_`-prefixed environment variables, with `__`
+/// between nested keys (e.g. `APP_APPLICATION__PORT=5001`).
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let mut setting = config::Config::default();
 
@@ -83,15 +270,20 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
 
     setting.merge(config::File::from(configuration_directory.join("base")).required(true))?;
 
-    let enviroment: Enviroment = std::env::var("APP_ENVIROMENT")
+    // `APP_ENVIROMENT` is the misspelled name we used to read: still honoured.
+    let enviroment: Enviroment = std::env::var("APP_ENVIRONMENT")
+        .or_else(|_| std::env::var("APP_ENVIROMENT"))
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIROMENT");
+        .expect("Failed to parse APP_ENVIRONMENT");
 
     setting.merge(
         config::File::from(configuration_directory.join(enviroment.as_str())).required(true),
     )?;
 
+    // Values are strings: numbers and booleans are parsed when deserializing.
+    setting.merge(config::Environment::with_prefix("app").separator("__"))?;
+
     setting.try_into()
 }
 
@@ -106,7 +298,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
     }
 
@@ -136,3 +328,128 @@ impl Settings {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use secrecy::ExposeSecret;
+
+    use super::{get_configuration, Settings};
+
+    /// Environment variables are shared by the whole process.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Reads the configuration with `vars` set, and no other `APP_` variable.
+    fn configuration_with_env(vars: &[(&str, &str)]) -> Result<Settings, config::ConfigError> {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous: Vec<_> = std::env::vars()
+            .filter(|(key, _)| key.starts_with("APP_"))
+            .collect();
+        for (key, _) in &previous {
+            std::env::remove_var(key);
+        }
+        for (key, value) in vars {
+            std::env::set_var(key, value);
+        }
+
+        let configuration = get_configuration();
+
+        for (key, _) in vars {
+            std::env::remove_var(key);
+        }
+        for (key, value) in previous {
+            std::env::set_var(key, value);
+        }
+        configuration
+    }
+
+    #[test]
+    fn environment_variables_override_the_yaml_files() {
+        let configuration =
+            configuration_with_env(&[("APP_APPLICATION__BASE_URL", "https://example.com")])
+                .unwrap();
+
+        assert_eq!(configuration.application.base_url, "https://example.com");
+    }
+
+    #[test]
+    fn the_yaml_files_apply_without_environment_variables() {
+        let configuration = configuration_with_env(&[]).unwrap();
+
+        // `local.yml` over `base.yml`.
+        assert_eq!(configuration.application.host, "127.0.0.1");
+        assert_eq!(configuration.application.port, 8000);
+    }
+
+    #[test]
+    fn numbers_are_parsed_from_environment_variables() {
+        let configuration = configuration_with_env(&[
+            ("APP_APPLICATION__PORT", "5001"),
+            ("APP_DATABASE__PORT", "6543"),
+            ("APP_EMAIL_CLIENT__TIMEOUT_MILLISECONDS", "2500"),
+            ("APP_TELEMETRY__OTLP__ENABLED", "true"),
+        ])
+        .unwrap();
+
+        assert_eq!(configuration.application.port, 5001);
+        assert_eq!(configuration.database.port, 6543);
+        assert_eq!(configuration.email_client.timeout_milliseconds, 2500);
+        assert!(configuration.telemetry.otlp.enabled);
+    }
+
+    #[test]
+    fn invalid_numbers_are_rejected() {
+        let configuration = configuration_with_env(&[("APP_APPLICATION__PORT", "eighty")]);
+
+        assert!(configuration.is_err());
+    }
+
+    const HMAC_SECRET: (&str, &str) = ("APP_APPLICATION__HMAC_SECRET", "a-production-secret");
+
+    #[test]
+    fn app_environment_selects_the_environment_file() {
+        let configuration =
+            configuration_with_env(&[("APP_ENVIRONMENT", "production"), HMAC_SECRET]).unwrap();
+
+        assert_eq!(configuration.application.host, "0.0.0.0");
+    }
+
+    #[test]
+    fn the_misspelled_app_enviroment_is_still_supported() {
+        let configuration =
+            configuration_with_env(&[("APP_ENVIROMENT", "production"), HMAC_SECRET]).unwrap();
+
+        assert_eq!(configuration.application.host, "0.0.0.0");
+    }
+
+    #[test]
+    fn environment_variables_override_the_environment_file() {
+        let configuration = configuration_with_env(&[
+            ("APP_ENVIRONMENT", "production"),
+            ("APP_APPLICATION__HOST", "10.0.0.1"),
+            HMAC_SECRET,
+        ])
+        .unwrap();
+
+        assert_eq!(configuration.application.host, "10.0.0.1");
+    }
+
+    #[test]
+    fn production_requires_the_hmac_secret_from_the_environment() {
+        let configuration = configuration_with_env(&[("APP_ENVIRONMENT", "production")]);
+
+        assert!(configuration.is_err());
+    }
+
+    #[test]
+    fn the_hmac_secret_is_read_from_the_environment() {
+        let configuration =
+            configuration_with_env(&[("APP_ENVIRONMENT", "production"), HMAC_SECRET]).unwrap();
+
+        assert_eq!(
+            configuration.application.hmac_secret.expose_secret(),
+            "a-production-secret"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+
+use crate::{
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    email_client::{EmailSender, EmailTemplates},
+    issue_delivery_worker::ExecutionOutcome,
+    metrics::{begin_transaction, ISSUE_DELIVERY_POOL},
+    routes::send_confirmation_email,
+};
+
+/// Confirmation emails that keep failing are dropped after this many attempts.
+const MAX_SEND_ATTEMPTS: i16 = 5;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct ConfirmationEmailTask {
+    subscription_token: String,
+    subscriber_email: String,
+    subscriber_name: String,
+    locale: String,
+    n_retries: i16,
+}
+
+/// Sends one of the confirmation emails queued by `/subscriptions` and
+/// `/subscriptions/resend`.
+#[tracing::instrument(skip_all, fields(subscriber_email = tracing::field::Empty), err)]
+pub async fn try_send_confirmation_email(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    email_templates: &EmailTemplates,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("subscriber_email", display(&task.subscriber_email));
+
+    let recipient = match parse_recipient(&task) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::warn!(
+                error.message = %e,
+                "Skipping a confirmation email. The stored contact details are invalid",
+            );
+            delete_task(&mut transaction, &task).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let locale = email_templates.negotiate_locale(Some(task.locale.as_str()));
+    match send_confirmation_email(
+        email_client,
+        email_templates,
+        &recipient,
+        locale,
+        base_url,
+        &task.subscription_token,
+    )
+    .await
+    {
+        Ok(()) => delete_task(&mut transaction, &task).await?,
+        Err(e) if task.n_retries + 1 >= MAX_SEND_ATTEMPTS => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a confirmation email. Giving up after {} attempts.",
+                MAX_SEND_ATTEMPTS
+            );
+            delete_task(&mut transaction, &task).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a confirmation email. It will be retried later.",
+            );
+            postpone_task(&mut transaction, &task).await?;
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+fn parse_recipient(task: &ConfirmationEmailTask) -> Result<NewSubscriber, String> {
+    Ok(NewSubscriber {
+        email: SubscriberEmail::parse(task.subscriber_email.clone())?,
+        name: SubscriberName::parse(task.subscriber_name.clone())?,
+    })
+}
+
+/// Locks a due task for the lifetime of the returned transaction.
+/// `SKIP LOCKED` lets other workers move on to a different one.
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, ConfirmationEmailTask)>, sqlx::Error> {
+    let mut transaction = begin_transaction(ISSUE_DELIVERY_POOL, pool).await?;
+    let task = sqlx::query_as!(
+        ConfirmationEmailTask,
+        r#"
+            SELECT
+                q.subscription_token,
+                s.email AS subscriber_email,
+                q.subscriber_name,
+                q.locale,
+                q.n_retries
+            FROM confirmation_email_queue q
+            JOIN subscription_tokens t ON t.subscription_token = q.subscription_token
+            JOIN subscriptions s ON s.id = t.subscriber_id
+            WHERE q.execute_after <= now()
+            FOR UPDATE OF q
+            SKIP LOCKED
+            LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &ConfirmationEmailTask,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM confirmation_email_queue WHERE subscription_token = $1"#,
+        task.subscription_token
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &ConfirmationEmailTask,
+) -> Result<(), sqlx::Error> {
+    let backoff = chrono::Duration::seconds(30 * 2_i64.pow(task.n_retries as u32));
+    sqlx::query!(
+        r#"
+            UPDATE confirmation_email_queue
+            SET
+                n_retries = n_retries + 1,
+                execute_after = $2
+            WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        Utc::now() + backoff
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{build_message, EmailError, EmailHeader, EmailSender},
+};
+
+/// Writes every email as an `.eml` file in a directory instead of sending it.
+/// Meant for local development: open the files with any mail client.
+pub struct FileSinkEmailSender {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkEmailSender {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        std::fs::create_dir_all(&directory).expect("Failed to create the email sink directory");
+
+        Self {
+            transport: AsyncFileTransport::new(directory),
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSinkEmailSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), EmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::{faker::internet::en::SafeEmail, Fake};
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{BatchRecipient, EmailSender, FileSinkEmailSender},
+    };
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_in_the_directory() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_sender = FileSinkEmailSender::new(directory.clone(), email());
+        let recipient = email();
+
+        let outcome = email_sender
+            .send_email(&recipient, "Welcome!", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert_ok!(outcome);
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: Welcome!"));
+        assert!(content.contains(recipient.as_ref()));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batch_messages_carry_the_one_click_unsubscribe_headers() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_sender = FileSinkEmailSender::new(directory.clone(), email());
+        let recipient = BatchRecipient {
+            email: email(),
+            unsubscribe_url: "https://example.com/unsubscribe?token=abc".into(),
+        };
+
+        let outcomes = email_sender
+            .send_batch(&[recipient], "Issue #1", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert!(outcomes.iter().all(Result::is_ok));
+
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let content = std::fs::read_to_string(file).unwrap();
+        assert!(content.contains("List-Unsubscribe: <https://example.com/unsubscribe?token=abc>"));
+        assert!(content.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+mod templates;
+
+pub use file_sink::FileSinkEmailSender;
+pub use postmark::{EmailClient, RetryPolicy, MAX_BATCH_SIZE};
+pub use smtp::SmtpEmailSender;
+pub use templates::{EmailTemplates, RenderedEmail, TemplateError};
+
+use std::sync::Arc;
+
+use lettre::{
+    message::{
+        header::{HeaderName, HeaderValue},
+        MultiPart,
+    },
+    Message,
+};
+
+use crate::domain::SubscriberEmail;
+
+/// Anything able to deliver an email on behalf of the application.
+///
+/// Handlers and the delivery worker only depend on this trait; which
+/// backend sits behind it is decided by `email_client.backend` in the
+/// configuration files.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Like `send_email`, adding `headers` on top of the ones every
+    /// message gets (`From`, `To`, `Subject`, ...).
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), EmailError>;
+
+    /// Sends the same email to every recipient, returning one outcome per
+    /// recipient in the same order, so that a single bad address does not
+    /// fail the whole batch.
+    /// Every message carries its recipient's one-click unsubscribe headers.
+    ///
+    /// Backends without a bulk API send the messages one by one.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<(), BatchSendError>> {
+        let mut outcomes = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let outcome = self
+                .send_email_with_headers(
+                    &recipient.email,
+                    subject,
+                    html_content,
+                    text_content,
+                    &recipient.unsubscribe_headers(),
+                )
+                .await
+                .map_err(|e| BatchSendError::RequestFailed(Arc::new(e)));
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+}
+
+/// A newsletter recipient, along with the link that unsubscribes them.
+#[derive(Debug)]
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    pub unsubscribe_url: String,
+}
+
+impl BatchRecipient {
+    /// `List-Unsubscribe` plus the RFC 8058 `List-Unsubscribe-Post` header,
+    /// which lets mail clients unsubscribe with a single POST.
+    pub fn unsubscribe_headers(&self) -> [EmailHeader; 2] {
+        [
+            EmailHeader {
+                name: "List-Unsubscribe",
+                value: format!("<{}>", self.unsubscribe_url),
+            },
+            EmailHeader {
+                name: "List-Unsubscribe-Post",
+                value: "List-Unsubscribe=One-Click".into(),
+            },
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailHeader {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// Why a single message of a batch was not delivered.
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum BatchSendError {
+    /// The provider refused this specific message (e.g. an inactive
+    /// recipient): sending it again will not help.
+    #[error("The message was rejected with error code {error_code}: {message}")]
+    Rejected { error_code: i64, message: String },
+    /// The request carrying this message failed as a whole.
+    #[error("The request carrying the message failed.")]
+    RequestFailed(#[source] Arc<EmailError>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error("Failed to send the email through the Postmark API.")]
+    Postmark(#[from] reqwest::Error),
+    #[error("Failed to deliver the email through SMTP.")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to write the email to the file sink.")]
+    FileSink(#[from] lettre::transport::file::Error),
+    #[error("Failed to build a valid email address.")]
+    InvalidAddress(#[from] lettre::address::AddressError),
+    #[error("Failed to build the email message.")]
+    InvalidMessage(#[from] lettre::error::Error),
+    #[error("Expected {expected} results from the batch endpoint, received {received}.")]
+    UnexpectedBatchResponse { expected: usize, received: usize },
+}
+
+/// Builds a multipart (plain text + HTML) RFC 5322 message, shared by the
+/// backends that speak MIME rather than a JSON API.
+fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[EmailHeader],
+) -> Result<Message, EmailError> {
+    let mut builder = Message::builder()
+        .from(sender.as_ref().parse()?)
+        .to(recipient.as_ref().parse()?)
+        .subject(subject);
+    for header in headers {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str(header.name),
+            header.value.clone(),
+        ));
+    }
+    let message = builder.multipart(MultiPart::alternative_plain_html(
+        text_content.to_owned(),
+        html_content.to_owned(),
+    ))?;
+    Ok(message)
+}
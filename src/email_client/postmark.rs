@@ -0,0 +1,729 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{BatchRecipient, BatchSendError, EmailError, EmailHeader, EmailSender},
+    metrics::METRICS,
+    telemetry::trace_context_headers,
+};
+
+/// Postmark accepts at most 500 messages per `/email/batch` call.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+impl<'a> From<&'a EmailHeader> for Header<'a> {
+    fn from(header: &'a EmailHeader) -> Self {
+        Self {
+            name: header.name,
+            value: &header.value,
+        }
+    }
+}
+
+/// One entry of the array returned by `/email/batch`, in request order.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchSendResult {
+    error_code: i64,
+    message: String,
+}
+
+/// How `EmailClient` retries requests that failed for transient reasons
+/// (timeouts, connection errors, 429 and 5xx responses).
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Pick a random delay between zero and the exponential backoff
+    /// ("full jitter") so that clients don't retry in lockstep.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Delay to wait after the given (1-based) failed attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+
+        if self.jitter {
+            capped.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+        } else {
+            capped
+        }
+    }
+}
+
+pub struct EmailClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl EmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+            retry_policy,
+        }
+    }
+}
+
+impl EmailClient {
+    /// POSTs `body` to `url`, retrying transient failures according to
+    /// the client's `RetryPolicy`.
+    async fn post_with_retry<T: serde::Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut attempt = 1;
+        loop {
+            let start = Instant::now();
+            let outcome = self
+                .http_client
+                .post(url)
+                .header(
+                    "X-Postmark-Server-Token",
+                    self.authorization_token.expose_secret(),
+                )
+                .headers(trace_context_headers())
+                .json(body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            METRICS.observe_email_attempt(outcome.is_ok(), start.elapsed());
+
+            match outcome {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt < self.retry_policy.max_attempts && is_retriable(&e) => {
+                    let delay = self.retry_policy.backoff(attempt);
+                    tracing::warn!(
+                        error.message = %e,
+                        attempt,
+                        retry_in_milliseconds = delay.as_millis() as u64,
+                        "Transient failure while sending an email, retrying",
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    METRICS.record_email_failure();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    async fn send_batch_chunk(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Vec<Result<(), BatchSendError>>, EmailError> {
+        let url = format!("{}/email/batch", self.base_url);
+
+        let headers: Vec<_> = recipients
+            .iter()
+            .map(BatchRecipient::unsubscribe_headers)
+            .collect();
+        let request_body: Vec<_> = recipients
+            .iter()
+            .zip(&headers)
+            .map(|(recipient, headers)| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: recipient.email.as_ref(),
+                subject,
+                html_body: html_content,
+                text_body: text_content,
+                headers: headers.iter().map(Header::from).collect(),
+            })
+            .collect();
+
+        let results: Vec<BatchSendResult> = self
+            .post_with_retry(&url, &request_body)
+            .await?
+            .json()
+            .await?;
+
+        if results.len() != recipients.len() {
+            return Err(EmailError::UnexpectedBatchResponse {
+                expected: recipients.len(),
+                received: results.len(),
+            });
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(()),
+                error_code => Err(BatchSendError::Rejected {
+                    error_code,
+                    message: result.message,
+                }),
+            })
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for EmailClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), EmailError> {
+        let url = format!("{}/email", self.base_url);
+
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers: headers.iter().map(Header::from).collect(),
+        };
+
+        self.post_with_retry(&url, &request_body).await?;
+
+        Ok(())
+    }
+
+    /// Sends through `/email/batch`, `MAX_BATCH_SIZE` messages per request.
+    async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<(), BatchSendError>> {
+        let mut outcomes = Vec::with_capacity(recipients.len());
+
+        for chunk in recipients.chunks(MAX_BATCH_SIZE) {
+            match self
+                .send_batch_chunk(chunk, subject, html_content, text_content)
+                .await
+            {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                Err(e) => {
+                    let e = Arc::new(e);
+                    outcomes.extend(
+                        chunk
+                            .iter()
+                            .map(|_| Err(BatchSendError::RequestFailed(e.clone()))),
+                    );
+                }
+            }
+        }
+
+        outcomes
+    }
+}
+
+/// Only failures that might go away on their own are worth retrying:
+/// a 4xx (e.g. 422 for an invalid recipient) will fail the same way again.
+fn is_retriable(e: &reqwest::Error) -> bool {
+    if e.is_timeout() || e.is_connect() {
+        return true;
+    }
+    match e.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use claim::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{
+            BatchRecipient, BatchSendError, EmailClient, EmailSender, RetryPolicy, MAX_BATCH_SIZE,
+        },
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                dbg!(&body);
+
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Replies to `/email/batch` with a successful result for every message.
+    struct BatchAcceptedResponder;
+
+    impl wiremock::Respond for BatchAcceptedResponder {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "To": message["To"],
+                    })
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..10).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn recipient() -> BatchRecipient {
+        BatchRecipient {
+            email: email(),
+            unsubscribe_url: format!(
+                "https://example.com/subscriptions/unsubscribe?unsubscribe_token={}",
+                uuid::Uuid::new_v4()
+            ),
+        }
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            retry_policy(),
+        )
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(10),
+            jitter: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_succeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let subscriber_email = email();
+        let subject: String = subject();
+        let content: String = content();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let subscriber_email = email();
+        let subject: String = subject();
+        let content: String = content();
+
+        // A 500 is retried until the policy runs out of attempts
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_timeout_if_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let subscriber_email = email();
+        let subject: String = subject();
+        let content: String = content();
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        let subscriber_email = email();
+
+        let subject: String = subject();
+
+        let content: String = content();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_transient_failures_until_it_succeeds() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_if_the_server_returns_429() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_if_the_server_returns_422() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_max_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(1000),
+            jitter: false,
+        };
+
+        assert_eq!(policy.backoff(1).as_millis(), 100);
+        assert_eq!(policy.backoff(2).as_millis(), 200);
+        assert_eq!(policy.backoff(3).as_millis(), 400);
+        assert_eq!(policy.backoff(5).as_millis(), 1000);
+        assert_eq!(policy.backoff(64).as_millis(), 1000);
+    }
+
+    #[test]
+    fn jittered_backoff_never_exceeds_the_max_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(1000),
+            jitter: true,
+        };
+
+        for attempt in 1..20 {
+            assert!(policy.backoff(attempt) <= policy.max_delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_every_message_to_the_batch_endpoint() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3).map(|_| recipient()).collect();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchAcceptedResponder)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(outcomes.len(), 3);
+        assert!(outcomes.iter().all(Result::is_ok));
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        for (message, recipient) in body.iter().zip(&recipients) {
+            assert_eq!(message["To"], recipient.email.as_ref());
+            assert!(message.get("From").is_some());
+            assert!(message.get("Subject").is_some());
+            assert!(message.get("HtmlBody").is_some());
+            assert!(message.get("TextBody").is_some());
+            assert_eq!(
+                message["Headers"],
+                serde_json::json!([
+                    {
+                        "Name": "List-Unsubscribe",
+                        "Value": format!("<{}>", recipient.unsubscribe_url)
+                    },
+                    {
+                        "Name": "List-Unsubscribe-Post",
+                        "Value": "List-Unsubscribe=One-Click"
+                    },
+                ])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_recipients_in_chunks_of_the_max_batch_size() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..MAX_BATCH_SIZE + 1).map(|_| recipient()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchAcceptedResponder)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(outcomes.len(), MAX_BATCH_SIZE + 1);
+        assert!(outcomes.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_individual_failures_without_failing_the_batch() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![recipient(), recipient()];
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "To": recipients[0].email.as_ref() },
+            {
+                "ErrorCode": 406,
+                "Message": "You tried to send to a recipient that has been marked as inactive."
+            }
+        ]));
+
+        Mock::given(path("/email/batch"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert!(outcomes[0].is_ok());
+        match &outcomes[1] {
+            Err(BatchSendError::Rejected { error_code, .. }) => assert_eq!(*error_code, 406),
+            other => panic!("Expected a rejected message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_a_failed_request_for_every_message_in_it() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![recipient(), recipient()];
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(matches!(outcome, Err(BatchSendError::RequestFailed(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_the_result_array_does_not_match_the_batch() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![recipient(), recipient()];
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert!(outcomes.iter().all(Result::is_err));
+    }
+}
@@ -0,0 +1,184 @@
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{build_message, EmailError, EmailHeader, EmailSender},
+};
+
+pub struct SmtpEmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailSender {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, Secret<String>)>,
+        require_tls: bool,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, EmailError> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        }
+        .port(port)
+        .timeout(Some(timeout));
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), EmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailSender, SmtpEmailSender},
+    };
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// A bare-bones SMTP server accepting a single message.
+    /// Replies to `RCPT TO` with `rcpt_reply` and hands back the
+    /// commands and data it received once the client quits.
+    async fn start_smtp_server(rcpt_reply: &'static str) -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut transcript = String::new();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                transcript.push_str(&line);
+                transcript.push('\n');
+
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 OK\r\n"
+                } else {
+                    let command = line.to_uppercase();
+                    if command.starts_with("EHLO") || command.starts_with("HELO") {
+                        b"250 localhost\r\n"
+                    } else if command.starts_with("RCPT TO") {
+                        rcpt_reply.as_bytes()
+                    } else if command.starts_with("DATA") {
+                        in_data = true;
+                        b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                    } else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"250 OK\r\n"
+                    }
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            let _ = tx.send(transcript);
+        });
+
+        (port, rx)
+    }
+
+    fn smtp_sender(port: u16) -> SmtpEmailSender {
+        SmtpEmailSender::new(
+            "127.0.0.1",
+            port,
+            None,
+            false,
+            email(),
+            std::time::Duration::from_secs(2),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_over_smtp() {
+        let (port, transcript) = start_smtp_server("250 OK\r\n").await;
+        let email_sender = smtp_sender(port);
+        let recipient = email();
+
+        let outcome = email_sender
+            .send_email(&recipient, "Welcome!", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert_ok!(outcome);
+
+        let transcript = transcript.await.unwrap();
+        assert!(transcript.contains(&format!("RCPT TO:<{}>", recipient.as_ref())));
+        assert!(transcript.contains("Subject: Welcome!"));
+        assert!(transcript.contains("Content-Type: text/plain"));
+        assert!(transcript.contains("Content-Type: text/html"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_rejects_the_recipient() {
+        let (port, _transcript) = start_smtp_server("550 No such user\r\n").await;
+        let email_sender = smtp_sender(port);
+
+        let outcome = email_sender
+            .send_email(&email(), "Welcome!", "<p>Hello</p>", "Hello")
+            .await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,285 @@
+use tera::{Context, Tera};
+
+use crate::{domain::SubscriberName, routes::error_chain_fmt};
+
+/// Every email is made of `<name>.subject.txt`, `<name>.html` and
+/// `<name>.txt`, in each locale folder: they are all checked when the
+/// templates are loaded, so a missing file stops the application at startup
+/// rather than at send time.
+const EMAILS: [&str; 2] = ["confirmation", "newsletter"];
+
+/// The copy of the emails sent by the application, loaded from the
+/// `email_client.templates_directory` folder.
+///
+/// Each sub-folder (`en/`, `es/`, ...) holds the emails of one locale; the
+/// layouts they extend sit at the root and are shared by all of them.
+/// `.html` templates escape every variable unless told otherwise, `.txt`
+/// templates (subjects and plain-text bodies) render them as they are.
+#[derive(Debug)]
+pub struct EmailTemplates {
+    tera: Tera,
+    locales: Vec<String>,
+    default_locale: String,
+}
+
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum TemplateError {
+    #[error("Failed to load the email templates.")]
+    LoadError(#[source] tera::Error),
+    #[error("The `{0}` email template is missing.")]
+    MissingTemplate(String),
+    #[error("There are no email templates for the default locale `{0}`.")]
+    UnknownDefaultLocale(String),
+    #[error("Failed to render the `{0}` email template.")]
+    RenderError(String, #[source] tera::Error),
+}
+
+impl std::fmt::Debug for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailTemplates {
+    pub fn load(directory: &str, default_locale: &str) -> Result<Self, TemplateError> {
+        let tera = Tera::new(&format!("{}/**/*", directory)).map_err(TemplateError::LoadError)?;
+
+        let loaded: Vec<&str> = tera.get_template_names().collect();
+        let mut locales: Vec<String> = loaded
+            .iter()
+            .filter_map(|name| name.split_once('/'))
+            .map(|(locale, _)| locale.to_string())
+            .collect();
+        locales.sort();
+        locales.dedup();
+
+        for locale in &locales {
+            for name in EMAILS
+                .iter()
+                .flat_map(|email| template_names(locale, email))
+            {
+                if !loaded.contains(&name.as_str()) {
+                    return Err(TemplateError::MissingTemplate(name));
+                }
+            }
+        }
+
+        let default_locale = locales
+            .iter()
+            .find(|locale| locale.eq_ignore_ascii_case(default_locale))
+            .cloned()
+            .ok_or_else(|| TemplateError::UnknownDefaultLocale(default_locale.into()))?;
+
+        Ok(Self {
+            tera,
+            locales,
+            default_locale,
+        })
+    }
+
+    /// Picks the first of `preferences` we have templates for, falling back
+    /// on the language alone (`es-MX` is served `es`) and eventually on the
+    /// default locale.
+    pub fn negotiate_locale<'a>(&self, preferences: impl IntoIterator<Item = &'a str>) -> &str {
+        preferences
+            .into_iter()
+            .find_map(|preference| {
+                let preference = preference.trim().replace('_', "-");
+                let language = preference.split('-').next().unwrap_or_default();
+                self.locales
+                    .iter()
+                    .find(|locale| locale.eq_ignore_ascii_case(&preference))
+                    .or_else(|| {
+                        self.locales
+                            .iter()
+                            .find(|locale| locale.eq_ignore_ascii_case(language))
+                    })
+            })
+            .unwrap_or(&self.default_locale)
+    }
+
+    pub fn confirmation(
+        &self,
+        locale: &str,
+        subscriber_name: &SubscriberName,
+        confirmation_link: &str,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let mut context = Context::new();
+        context.insert("subscriber_name", subscriber_name.as_ref());
+        context.insert("confirmation_link", confirmation_link);
+        self.render(locale, "confirmation", context)
+    }
+
+    /// Wraps the content of a newsletter issue in the shared layout.
+    /// `html_content` is written by an admin and is inserted as it is.
+    pub fn newsletter(
+        &self,
+        locale: &str,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let mut context = Context::new();
+        context.insert("title", title);
+        context.insert("html_content", html_content);
+        context.insert("text_content", text_content);
+        self.render(locale, "newsletter", context)
+    }
+
+    fn render(
+        &self,
+        locale: &str,
+        email: &str,
+        mut context: Context,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let locale = self.negotiate_locale([locale]);
+        context.insert("locale", locale);
+        let [subject, html, text] = template_names(locale, email).map(|name| {
+            self.tera
+                .render(&name, &context)
+                .map_err(|e| TemplateError::RenderError(name, e))
+        });
+        Ok(RenderedEmail {
+            subject: subject?.trim().to_string(),
+            html: html?,
+            text: text?,
+        })
+    }
+}
+
+fn template_names(locale: &str, email: &str) -> [String; 3] {
+    [
+        format!("{}/{}.subject.txt", locale, email),
+        format!("{}/{}.html", locale, email),
+        format!("{}/{}.txt", locale, email),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::{EmailTemplates, TemplateError};
+    use crate::domain::SubscriberName;
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load("templates/emails", "en").expect("Failed to load the email templates")
+    }
+
+    #[test]
+    fn the_templates_shipped_with_the_application_are_valid() {
+        assert_ok!(EmailTemplates::load("templates/emails", "en"));
+        assert_ok!(EmailTemplates::load("templates/emails", "es"));
+    }
+
+    #[test]
+    fn loading_fails_if_an_email_template_is_missing() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(directory.join("en")).unwrap();
+        std::fs::write(directory.join("en/confirmation.html"), "Hello").unwrap();
+
+        let result = EmailTemplates::load(directory.to_str().unwrap(), "en");
+
+        assert!(matches!(result, Err(TemplateError::MissingTemplate(_))));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn loading_fails_if_the_directory_holds_an_invalid_template() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("layout.html"), "{% if %}").unwrap();
+
+        assert_err!(EmailTemplates::load(directory.to_str().unwrap(), "en"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn loading_fails_if_the_default_locale_has_no_templates() {
+        let result = EmailTemplates::load("templates/emails", "fr");
+
+        assert!(matches!(
+            result,
+            Err(TemplateError::UnknownDefaultLocale(_))
+        ));
+    }
+
+    #[test]
+    fn the_first_supported_preference_is_picked() {
+        let templates = templates();
+
+        assert_eq!(templates.negotiate_locale(["fr", "es", "en"]), "es");
+        assert_eq!(templates.negotiate_locale(["ES"]), "es");
+    }
+
+    #[test]
+    fn a_regional_preference_falls_back_on_its_language() {
+        assert_eq!(templates().negotiate_locale(["es-MX"]), "es");
+        assert_eq!(templates().negotiate_locale(["es_AR"]), "es");
+    }
+
+    #[test]
+    fn unknown_preferences_fall_back_on_the_default_locale() {
+        assert_eq!(templates().negotiate_locale(["fr", "*"]), "en");
+        assert_eq!(templates().negotiate_locale([]), "en");
+    }
+
+    #[test]
+    fn the_confirmation_email_contains_the_name_and_the_link() {
+        let name = SubscriberName::parse("Ursula".into()).unwrap();
+        let link = "http://127.0.0.1/subscriptions/confirm?subscription_token=abc";
+
+        let email = templates().confirmation("en", &name, link).unwrap();
+
+        assert_eq!(email.subject, "Welcome!");
+        for body in [&email.html, &email.text] {
+            assert!(body.contains("Ursula"));
+            assert!(body.contains(link));
+            assert!(body.contains("Welcome to our newsletter!"));
+        }
+    }
+
+    #[test]
+    fn the_confirmation_email_is_translated() {
+        let name = SubscriberName::parse("Ursula".into()).unwrap();
+
+        let email = templates()
+            .confirmation("es", &name, "http://link")
+            .unwrap();
+
+        assert_eq!(email.subject, "¡Bienvenido!");
+        assert!(email.html.contains(r#"<html lang="es">"#));
+        assert!(email.text.contains("¡Bienvenido a nuestro boletín!"));
+    }
+
+    #[test]
+    fn the_subscriber_name_is_escaped_in_the_html_body_only() {
+        let name = SubscriberName::parse("Ursula & Le Guin".into()).unwrap();
+
+        let email = templates()
+            .confirmation("en", &name, "http://link")
+            .unwrap();
+
+        assert!(email.html.contains("Ursula &amp; Le Guin"));
+        assert!(email.text.contains("Ursula & Le Guin"));
+    }
+
+    #[test]
+    fn the_newsletter_content_is_wrapped_in_the_layout() {
+        let email = templates()
+            .newsletter("en", "Issue #1", "<p>Hello</p>", "Hello")
+            .unwrap();
+
+        assert_eq!(email.subject, "Issue #1");
+        assert!(email.html.contains("<p>Hello</p>"));
+        assert!(email.html.starts_with("<!DOCTYPE html>"));
+        assert!(email.text.starts_with("Hello"));
+    }
+}
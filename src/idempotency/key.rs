@@ -0,0 +1,88 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+
+use crate::idempotency::IdempotencyError;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 50;
+
+/// Client-chosen key identifying a mutating request across retries,
+/// taken from the `Idempotency-Key` header.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = IdempotencyError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.trim().is_empty() {
+            return Err(IdempotencyError::InvalidKey(
+                "The idempotency key cannot be empty.".into(),
+            ));
+        }
+        if s.len() >= MAX_KEY_LENGTH {
+            return Err(IdempotencyError::InvalidKey(format!(
+                "The idempotency key must be shorter than {} characters.",
+                MAX_KEY_LENGTH
+            )));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<IdempotencyKey> for String {
+    fn from(k: IdempotencyKey) -> Self {
+        k.0
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for IdempotencyKey {
+    type Error = IdempotencyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let key = req
+            .headers()
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .ok_or(IdempotencyError::MissingKey)
+            .and_then(|value| {
+                value.to_str().map_err(|_| {
+                    IdempotencyError::InvalidKey(
+                        "The idempotency key must be a valid ASCII string.".into(),
+                    )
+                })
+            })
+            .and_then(|value| value.to_owned().try_into());
+        ready(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::IdempotencyKey;
+
+    #[test]
+    fn an_empty_key_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("".to_string()));
+        assert_err!(IdempotencyKey::try_from("   ".to_string()));
+    }
+
+    #[test]
+    fn a_key_of_50_characters_or_more_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("a".repeat(50)));
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        assert_ok!(IdempotencyKey::try_from(uuid::Uuid::new_v4().to_string()));
+    }
+}
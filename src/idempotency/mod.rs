@@ -0,0 +1,63 @@
+//! Replay-safe handling of mutating requests.
+//!
+//! A handler opts in by extracting an [`IdempotencyKey`], calling
+//! [`try_processing`] before doing any work and handing its response to
+//! [`save_response`], which also commits the transaction it ran in.
+
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{save_response, try_processing, NextAction};
+
+use actix_web::{http::StatusCode, ResponseError};
+
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("The 'Idempotency-Key' header is missing.")]
+    MissingKey,
+    #[error("{0}")]
+    InvalidKey(String),
+    #[error("A request with the same idempotency key is still being processed.")]
+    RequestInProgress,
+    #[error("Failed to start a SQL transaction to process an idempotent request.")]
+    TransactionStartError(#[source] sqlx::Error),
+    #[error("Failed to store the idempotency key.")]
+    InsertKeyError(#[source] sqlx::Error),
+    #[error("Failed to retrieve the saved response for an idempotency key.")]
+    GetSavedResponseError(#[source] sqlx::Error),
+    #[error("The saved response has an invalid status code: {0}.")]
+    InvalidSavedStatusCode(i16),
+    #[error("Failed to read the body of the response to save: {0}")]
+    ReadBodyError(String),
+    #[error("Failed to save the response for an idempotency key.")]
+    SaveResponseError(#[source] sqlx::Error),
+    #[error("Failed to commit SQL transaction to save an idempotent response.")]
+    TransactionCommitError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for IdempotencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for IdempotencyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IdempotencyError::MissingKey | IdempotencyError::InvalidKey(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            IdempotencyError::RequestInProgress => StatusCode::CONFLICT,
+            IdempotencyError::TransactionStartError(_)
+            | IdempotencyError::InsertKeyError(_)
+            | IdempotencyError::GetSavedResponseError(_)
+            | IdempotencyError::InvalidSavedStatusCode(_)
+            | IdempotencyError::ReadBodyError(_)
+            | IdempotencyError::SaveResponseError(_)
+            | IdempotencyError::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    idempotency::{IdempotencyError, IdempotencyKey},
+    metrics::{begin_transaction, API_POOL},
+};
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+pub enum NextAction {
+    /// First time we see this key: do the work inside this transaction,
+    /// then pass it to [`save_response`].
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claim `idempotency_key` for `user_id`.
+///
+/// A concurrent request holding the same key blocks the insert until it
+/// commits, so duplicates wait for the first response and replay it.
+#[tracing::instrument(name = "Try processing an idempotent request", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, IdempotencyError> {
+    let mut transaction = begin_transaction(API_POOL, pool)
+        .await
+        .map_err(IdempotencyError::TransactionStartError)?;
+
+    let n_inserted_rows = sqlx::query!(
+        r#"
+            INSERT INTO idempotency (user_id, idempotency_key, created_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(IdempotencyError::InsertKeyError)?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        return Ok(NextAction::StartProcessing(transaction));
+    }
+
+    match get_saved_response(pool, idempotency_key, user_id).await? {
+        Some(saved_response) => Ok(NextAction::ReturnSavedResponse(saved_response)),
+        // The key is claimed but no response was recorded for it.
+        None => Err(IdempotencyError::RequestInProgress),
+    }
+}
+
+#[tracing::instrument(name = "Get saved response", skip(pool))]
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, IdempotencyError> {
+    let saved_response = sqlx::query!(
+        r#"
+            SELECT
+                response_status_code,
+                response_headers AS "response_headers: Vec<HeaderPairRecord>",
+                response_body
+            FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(IdempotencyError::GetSavedResponseError)?;
+
+    let Some(r) = saved_response else {
+        return Ok(None);
+    };
+    let (Some(status_code), Some(headers), Some(body)) =
+        (r.response_status_code, r.response_headers, r.response_body)
+    else {
+        return Ok(None);
+    };
+
+    let status_code = u16::try_from(status_code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or(IdempotencyError::InvalidSavedStatusCode(status_code))?;
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in headers {
+        response.append_header((name, value));
+    }
+    Ok(Some(response.body(body)))
+}
+
+/// Record `http_response` for the key claimed by [`try_processing`] and
+/// commit the transaction the request was processed in.
+#[tracing::instrument(name = "Save idempotent response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, IdempotencyError> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| IdempotencyError::ReadBodyError(e.to_string()))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers: Vec<HeaderPairRecord> = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect();
+
+    // `query_unchecked!` because the macros can't check composite types.
+    sqlx::query_unchecked!(
+        r#"
+            UPDATE idempotency
+            SET
+                response_status_code = $3,
+                response_headers = $4,
+                response_body = $5
+            WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(IdempotencyError::SaveResponseError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(IdempotencyError::TransactionCommitError)?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
@@ -0,0 +1,351 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    confirmation_email_worker::try_send_confirmation_email,
+    domain::SubscriberEmail,
+    email_client::{BatchRecipient, BatchSendError, EmailSender, EmailTemplates, TemplateError},
+    metrics::{begin_transaction, ISSUE_DELIVERY_POOL, METRICS},
+    shutdown::Shutdown,
+    startup::get_connection_pool,
+};
+
+/// Deliveries that keep failing are dropped after this many attempts.
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+
+/// How many deliveries of the same issue are sent in a single batch.
+const DELIVERY_BATCH_SIZE: i64 = 100;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Sends the queued confirmation emails and newsletter issues.
+///
+/// Once `shutdown` is requested, finishes the batch in progress, if any,
+/// and closes its database pool.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: Shutdown,
+) -> Result<(), std::io::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    METRICS.register_pool(ISSUE_DELIVERY_POOL, &connection_pool);
+    let email_templates = configuration
+        .email_client
+        .templates(&configuration.application.default_locale)
+        .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        connection_pool,
+        email_client,
+        email_templates,
+        configuration.application.base_url,
+        shutdown,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    email_templates: EmailTemplates,
+    base_url: String,
+    mut shutdown: Shutdown,
+) -> Result<(), std::io::Error> {
+    while !shutdown.is_requested() {
+        // Confirmation emails first: someone is waiting for them.
+        let confirmation =
+            try_send_confirmation_email(&pool, email_client.as_ref(), &email_templates, &base_url)
+                .await;
+        let issue =
+            try_execute_task(&pool, email_client.as_ref(), &email_templates, &base_url).await;
+        let pause = match (confirmation, issue) {
+            (Ok(ExecutionOutcome::TaskCompleted), _) | (_, Ok(ExecutionOutcome::TaskCompleted)) => {
+                continue
+            }
+            (Err(_), _) | (_, Err(_)) => Duration::from_secs(1),
+            (Ok(ExecutionOutcome::EmptyQueue), Ok(ExecutionOutcome::EmptyQueue)) => {
+                Duration::from_secs(10)
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+    pool.close().await;
+    Ok(())
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        n_deliveries = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    email_templates: &EmailTemplates,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, issue_id, tasks)) = dequeue_tasks(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("n_deliveries", tasks.len());
+
+    // Subscribers get the issue in their own locale: one batch per locale.
+    let mut batches: BTreeMap<&str, (Vec<BatchRecipient>, Vec<DeliveryTask>)> = BTreeMap::new();
+    for task in tasks {
+        let Some(unsubscribe_token) = task.unsubscribe_token.clone() else {
+            tracing::warn!(
+                subscriber_email = %task.subscriber_email,
+                "Skipping a delivery to an address that is no longer subscribed.",
+            );
+            delete_task(&mut transaction, issue_id, &task).await?;
+            continue;
+        };
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => {
+                let locale = email_templates.negotiate_locale(task.locale.as_deref());
+                let (recipients, deliverable_tasks) = batches.entry(locale).or_default();
+                recipients.push(BatchRecipient {
+                    email,
+                    unsubscribe_url: format!(
+                        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                        base_url, unsubscribe_token
+                    ),
+                });
+                deliverable_tasks.push(task);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                delete_task(&mut transaction, issue_id, &task).await?;
+            }
+        }
+    }
+
+    if !batches.is_empty() {
+        let issue = get_issue(pool, issue_id).await?;
+        // Every locale is rendered before the first email goes out: a broken
+        // template must not roll back the bookkeeping of emails already sent.
+        let batches = batches
+            .into_iter()
+            .map(|(locale, (recipients, tasks))| {
+                let email = email_templates.newsletter(
+                    locale,
+                    &issue.title,
+                    &issue.html_content,
+                    &issue.text_content,
+                )?;
+                Ok((email, recipients, tasks))
+            })
+            .collect::<Result<Vec<_>, TemplateError>>()?;
+
+        for (email, recipients, deliverable_tasks) in batches {
+            let outcomes = email_client
+                .send_batch(&recipients, &email.subject, &email.html, &email.text)
+                .await;
+
+            for (task, outcome) in deliverable_tasks.iter().zip(outcomes) {
+                match outcome {
+                    Ok(()) => delete_task(&mut transaction, issue_id, task).await?,
+                    Err(e) => handle_failed_delivery(&mut transaction, issue_id, task, e).await?,
+                }
+            }
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn handle_failed_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    task: &DeliveryTask,
+    e: BatchSendError,
+) -> Result<(), sqlx::Error> {
+    match e {
+        BatchSendError::Rejected { .. } => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "The email provider rejected the delivery of an issue to a confirmed subscriber.",
+            );
+            delete_task(transaction, issue_id, task).await
+        }
+        BatchSendError::RequestFailed(_) if task.n_retries + 1 >= MAX_DELIVERY_ATTEMPTS => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Giving up after {} attempts.",
+                MAX_DELIVERY_ATTEMPTS
+            );
+            delete_task(transaction, issue_id, task).await
+        }
+        BatchSendError::RequestFailed(_) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to deliver issue to a confirmed subscriber. \
+                The delivery will be retried later.",
+            );
+            postpone_task(transaction, issue_id, task).await
+        }
+    }
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    subscriber_email: String,
+    n_retries: i16,
+    /// `None` if the subscriber is gone, or no longer confirmed.
+    unsubscribe_token: Option<String>,
+    locale: Option<String>,
+}
+
+/// Locks up to `DELIVERY_BATCH_SIZE` due tasks of a single issue for the
+/// lifetime of the returned transaction.
+/// `SKIP LOCKED` lets other workers, in this process or in another replica,
+/// move on to different rows instead of delivering the same ones twice.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Uuid, Vec<DeliveryTask>)>, sqlx::Error> {
+    let mut transaction = begin_transaction(ISSUE_DELIVERY_POOL, pool).await?;
+    let issue = sqlx::query!(
+        r#"
+            SELECT newsletter_issue_id
+            FROM issue_delivery_queue
+            WHERE execute_after <= now()
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some(issue) = issue else {
+        return Ok(None);
+    };
+
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+            SELECT
+                q.subscriber_email,
+                q.n_retries,
+                s.unsubscribe_token AS "unsubscribe_token?",
+                s.locale
+            FROM issue_delivery_queue q
+            LEFT JOIN subscriptions s
+                ON s.email = q.subscriber_email AND s.status = 'confirmed'
+            WHERE
+                q.newsletter_issue_id = $1 AND
+                q.execute_after <= now()
+            FOR UPDATE OF q
+            SKIP LOCKED
+            LIMIT $2
+        "#,
+        issue.newsletter_issue_id,
+        DELIVERY_BATCH_SIZE
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    Ok(Some((transaction, issue.newsletter_issue_id, tasks)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    task: &DeliveryTask,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM issue_delivery_queue
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+        "#,
+        issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    task: &DeliveryTask,
+) -> Result<(), sqlx::Error> {
+    let backoff = chrono::Duration::seconds(30 * 2_i64.pow(task.n_retries as u32));
+    sqlx::query!(
+        r#"
+            UPDATE issue_delivery_queue
+            SET
+                n_retries = n_retries + 1,
+                execute_after = $3
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+        "#,
+        issue_id,
+        task.subscriber_email,
+        Utc::now() + backoff
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+            SELECT title, text_content, html_content
+            FROM newsletter_issues
+            WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
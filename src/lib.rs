@@ -1,6 +1,16 @@
+pub mod authentication;
 pub mod configuration;
+pub mod confirmation_email_worker;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod shutdown;
 pub mod startup;
+pub mod subscription_cleanup_worker;
 pub mod telemetry;
+pub mod utils;
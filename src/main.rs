@@ -1,21 +1,77 @@
-use std::io::stdout;
+use opentelemetry::trace::TracerProvider;
+use std::{fmt::Debug, fmt::Display, io::stdout};
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
+    shutdown::{shutdown_signal, ShutdownController, SHUTDOWN_MARGIN},
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    subscription_cleanup_worker::run_cleanup_until_stopped,
+    telemetry::{get_subscriber, init_subscriber, init_tracer_provider},
 };
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let configuration = get_configuration().expect("Failed to read configuration");
+
     // Log's configuration
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), stdout);
+    let tracer_provider = init_tracer_provider("zero2prod", &configuration.telemetry.otlp)
+        .expect("Failed to build the OTLP span exporter");
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        stdout,
+        tracer_provider
+            .as_ref()
+            .map(|provider| provider.tracer("zero2prod")),
+    );
     init_subscriber(subscriber);
 
-    let configuration = get_configuration().expect("Failed to read configuration");
+    let shutdown = ShutdownController::new();
+    let grace_period = configuration.application.shutdown_grace_period();
+    let application = Application::build(configuration.clone()).await?;
 
-    let application = Application::build(configuration).await?;
+    let mut application_task = tokio::spawn(application.run_until_stopped(shutdown.subscribe()));
+    let mut worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration.clone(),
+        shutdown.subscribe(),
+    ));
+    let mut cleanup_task = tokio::spawn(run_cleanup_until_stopped(
+        configuration,
+        shutdown.subscribe(),
+    ));
 
-    application.run_until_stopped().await?;
+    tokio::select! {
+        o = &mut application_task => report_exit("API", o),
+        o = &mut worker_task => report_exit("Background worker", o),
+        o = &mut cleanup_task => report_exit("Subscription cleanup", o),
+        _ = shutdown_signal() => tracing::info!("Received a shutdown signal"),
+    };
+
+    // Whatever stopped first, the other tasks get to finish their work.
+    shutdown.trigger();
+    let drain = async {
+        for (task_name, task) in [
+            ("API", application_task),
+            ("Background worker", worker_task),
+            ("Subscription cleanup", cleanup_task),
+        ] {
+            if !task.is_finished() {
+                report_exit(task_name, task.await);
+            }
+        }
+    };
+    if tokio::time::timeout(grace_period + SHUTDOWN_MARGIN, drain)
+        .await
+        .is_err()
+    {
+        tracing::warn!("Exiting with tasks still running after the grace period");
+    }
+
+    // Flushes the spans still waiting to be exported.
+    if let Some(provider) = tracer_provider {
+        let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+    }
 
     /* let connection_pool = PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -54,3 +110,27 @@ async fn main() -> std::io::Result<()> {
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Every metric of the process, exposed on `/metrics`.
+///
+/// A single instance is shared by the API, the background workers and the
+/// email clients, whatever the number of `App`s or pools built.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Values of the `pool` label.
+pub const API_POOL: &str = "api";
+pub const ISSUE_DELIVERY_POOL: &str = "issue_delivery";
+pub const SUBSCRIPTION_CLEANUP_POOL: &str = "subscription_cleanup";
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    email_send_attempts_total: IntCounterVec,
+    email_send_failures_total: IntCounter,
+    email_send_duration_seconds: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGaugeVec,
+    db_pool_acquire_wait_seconds: HistogramVec,
+    /// Read at every scrape, keyed by the `pool` label.
+    pools: Mutex<HashMap<&'static str, PgPool>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests.",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let email_send_attempts_total = IntCounterVec::new(
+            Opts::new(
+                "email_send_attempts_total",
+                "Number of requests made to the email provider, retries included.",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let email_send_failures_total = IntCounter::new(
+            "email_send_failures_total",
+            "Number of emails, or batches of emails, given up on after the last retry.",
+        )
+        .unwrap();
+        let email_send_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "email_send_duration_seconds",
+                "Duration of the requests made to the email provider.",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Connections of the database pools."),
+            &["pool", "state"],
+        )
+        .unwrap();
+        let db_pool_max_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_max_connections",
+                "Maximum size of the database pools.",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+        let db_pool_acquire_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "db_pool_acquire_wait_seconds",
+                "Time spent waiting for a connection to begin a transaction.",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+
+        for collector in [
+            Box::new(http_requests_total.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_request_duration_seconds.clone()),
+            Box::new(email_send_attempts_total.clone()),
+            Box::new(email_send_failures_total.clone()),
+            Box::new(email_send_duration_seconds.clone()),
+            Box::new(db_pool_connections.clone()),
+            Box::new(db_pool_max_connections.clone()),
+            Box::new(db_pool_acquire_wait_seconds.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("Metric names are unique");
+        }
+
+        Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            email_send_attempts_total,
+            email_send_failures_total,
+            email_send_duration_seconds,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_pool_acquire_wait_seconds,
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn observe_http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests_total.with_label_values(&labels).inc();
+        self.http_request_duration_seconds
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// One request to the email provider, whether it will be retried or not.
+    pub fn observe_email_attempt(&self, succeeded: bool, elapsed: Duration) {
+        let outcome = if succeeded { "success" } else { "failure" };
+        self.email_send_attempts_total
+            .with_label_values(&[outcome])
+            .inc();
+        self.email_send_duration_seconds
+            .with_label_values(&[outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_email_failure(&self) {
+        self.email_send_failures_total.inc();
+    }
+
+    /// Reports `pool` under the `pool` label from now on, replacing the
+    /// pool previously registered with the same name.
+    pub fn register_pool(&self, name: &'static str, pool: &PgPool) {
+        self.pools
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name, pool.clone());
+    }
+
+    /// Reads the state the pools keep track of, without touching a
+    /// connection: a scrape must not compete with the requests for one.
+    pub fn observe_pools(&self) {
+        let pools = self.pools.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, pool) in pools.iter() {
+            let idle = pool.num_idle() as i64;
+            let size = pool.size() as i64;
+            self.db_pool_connections
+                .with_label_values(&[name, "idle"])
+                .set(idle);
+            self.db_pool_connections
+                .with_label_values(&[name, "in_use"])
+                .set(size - idle);
+            self.db_pool_max_connections
+                .with_label_values(&[name])
+                .set(pool.options().get_max_connections() as i64);
+        }
+    }
+
+    pub fn observe_pool_acquire(&self, pool_name: &str, elapsed: Duration) {
+        self.db_pool_acquire_wait_seconds
+            .with_label_values(&[pool_name])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// The registry in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("The text format is valid UTF-8"))
+    }
+}
+
+/// `pool.begin()`, recording how long it took under the `pool` label.
+///
+/// sqlx does not expose the time callers queue for a connection: timing
+/// the acquisitions we make anyway, rather than probing the pool on scrape,
+/// keeps the metric from competing with the requests it measures. The
+/// `BEGIN` round trip is included.
+pub async fn begin_transaction(
+    pool_name: &str,
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let start = Instant::now();
+    let transaction = pool.begin().await;
+    METRICS.observe_pool_acquire(pool_name, start.elapsed());
+    transaction
+}
+
+/// Counts and times every request, labelled by the pattern of the matched
+/// route (`/admin/dashboard`, not the actual path) to keep the number of
+/// series bounded.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let start = Instant::now();
+
+    let result = next.call(req).await;
+
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    METRICS.observe_http_request(&method, &route, status.as_u16(), start.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::{begin_transaction, record_http_metrics, METRICS};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn requests_are_labelled_with_the_route_pattern() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(record_http_metrics))
+                .route("/metrics-test/items/{id}", web::get().to(ok)),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/metrics-test/items/42")
+            .to_request();
+        call_service(&app, request).await;
+
+        let rendered = METRICS.render().unwrap();
+        assert!(rendered.contains(
+            r#"http_requests_total{method="GET",route="/metrics-test/items/{id}",status="200"} 1"#
+        ));
+        assert!(!rendered.contains("/metrics-test/items/42"));
+    }
+
+    #[actix_web::test]
+    async fn unmatched_requests_share_a_single_label() {
+        let app = init_service(App::new().wrap(from_fn(record_http_metrics))).await;
+
+        let request = TestRequest::get()
+            .uri("/metrics-test/does-not-exist")
+            .to_request();
+        call_service(&app, request).await;
+
+        let rendered = METRICS.render().unwrap();
+        assert!(rendered.contains(r#"route="unmatched",status="404""#));
+        assert!(!rendered.contains("/metrics-test/does-not-exist"));
+    }
+
+    #[test]
+    fn email_attempts_are_counted_by_outcome() {
+        METRICS.observe_email_attempt(false, std::time::Duration::from_millis(5));
+
+        let rendered = METRICS.render().unwrap();
+        assert!(rendered.contains(r#"email_send_attempts_total{outcome="failure"}"#));
+        assert!(rendered.contains(r#"email_send_duration_seconds_count{outcome="failure"}"#));
+    }
+
+    #[tokio::test]
+    async fn pools_are_observed_without_opening_a_connection() {
+        let pool = PgPoolOptions::new()
+            .max_connections(3)
+            .connect_lazy("postgres://nobody@127.0.0.1:1/nowhere")
+            .unwrap();
+        METRICS.register_pool("metrics_test", &pool);
+
+        METRICS.observe_pools();
+
+        let rendered = METRICS.render().unwrap();
+        assert!(rendered.contains(r#"db_pool_connections{pool="metrics_test",state="idle"} 0"#));
+        assert!(rendered.contains(r#"db_pool_connections{pool="metrics_test",state="in_use"} 0"#));
+        assert!(rendered.contains(r#"db_pool_max_connections{pool="metrics_test"} 3"#));
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn transactions_record_the_time_spent_waiting_for_a_connection() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("postgres://nobody@127.0.0.1:1/nowhere")
+            .unwrap();
+
+        // Failed acquisitions are the ones worth seeing.
+        assert!(begin_transaction("metrics_acquire_test", &pool)
+            .await
+            .is_err());
+
+        let rendered = METRICS.render().unwrap();
+        assert!(rendered
+            .contains(r#"db_pool_acquire_wait_seconds_count{pool="metrics_acquire_test"} 1"#));
+    }
+}
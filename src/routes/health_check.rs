@@ -1,5 +1,187 @@
-use actix_web::HttpResponse;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
 
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::{migrate::Migrator, PgPool};
+
+/// The migrations embedded in the binary, to compare the database against.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Kept for the probes already pointing at it: same as `/health/live`.
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    responses((status = 200, description = "The application is up."))
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Liveness: the process is up and serving requests.
+///
+/// It does not look at the dependencies: restarting the instance would not
+/// bring Postgres back.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The application is up."))
+)]
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Reachability of the email API, added to the readiness checks when enabled.
+pub struct EmailApiProbe {
+    base_url: String,
+    http_client: reqwest::Client,
+}
+
+impl EmailApiProbe {
+    pub fn new(base_url: String, timeout: Duration) -> Self {
+        let http_client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            base_url,
+            http_client,
+        }
+    }
+
+    /// Any HTTP response will do: we are not authenticated, and only care
+    /// about the API answering.
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        self.http_client
+            .get(&self.base_url)
+            .send()
+            .await
+            .context("Failed to reach the email API")?;
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ReadinessReport {
+    /// `ready`, or `unavailable` when a critical check failed.
+    status: &'static str,
+    #[schema(value_type = BTreeMap<String, CheckReport>)]
+    checks: BTreeMap<&'static str, CheckReport>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CheckReport {
+    /// `up` or `down`.
+    status: &'static str,
+    /// Whether the instance is unavailable when the check fails.
+    critical: bool,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Readiness: whether the instance can serve traffic, with the outcome of
+/// each check.
+///
+/// The database must answer and be migrated at least up to the latest
+/// migration of the binary. The email API, when checked, is not critical:
+/// deliveries are queued and retried.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The instance can serve traffic.", body = ReadinessReport),
+        (status = 503, description = "A critical dependency is failing.", body = ReadinessReport),
+    )
+)]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    email_api: web::Data<Option<EmailApiProbe>>,
+) -> HttpResponse {
+    let (database, migrations, email_api) = tokio::join!(
+        run_check(true, check_database(&pool)),
+        run_check(true, check_migrations(&pool)),
+        async {
+            match email_api.as_ref() {
+                Some(probe) => Some(run_check(false, probe.check()).await),
+                None => None,
+            }
+        },
+    );
+
+    let mut checks = BTreeMap::from([("database", database), ("migrations", migrations)]);
+    if let Some(email_api) = email_api {
+        checks.insert("email_api", email_api);
+    }
+
+    let ready = checks
+        .values()
+        .all(|check| !check.critical || check.error.is_none());
+    let report = ReadinessReport {
+        status: if ready { "ready" } else { "unavailable" },
+        checks,
+    };
+    if ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+async fn run_check(
+    critical: bool,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let outcome = check.await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    match outcome {
+        Ok(()) => CheckReport {
+            status: "up",
+            critical,
+            latency_ms,
+            error: None,
+        },
+        Err(e) => CheckReport {
+            status: "down",
+            critical,
+            latency_ms,
+            error: Some(format!("{:#}", e)),
+        },
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS ready")
+        .fetch_one(pool)
+        .await
+        .context("Failed to query the database")?;
+    Ok(())
+}
+
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let expected = MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+    // Not checked at compile time: sqlx creates the table when migrating.
+    let applied: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(pool)
+            .await
+            .context("Failed to read the applied migrations")?;
+
+    match applied {
+        Some(applied) if applied >= expected => Ok(()),
+        Some(applied) => Err(anyhow::anyhow!(
+            "The database is at migration {}, {} is expected",
+            applied,
+            expected
+        )),
+        None => Err(anyhow::anyhow!("No migration has been applied")),
+    }
+}
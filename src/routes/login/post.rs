@@ -0,0 +1,77 @@
+use actix_web::{error::InternalError, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    routes::error_chain_fmt,
+    session_state::TypedSession,
+    utils::see_other,
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed.")]
+    InvalidCredentials(#[source] AuthError),
+    #[error("Something went wrong.")]
+    AuthenticationError(#[source] AuthError),
+    #[error("Something went wrong.")]
+    SessionError(#[source] actix_session::SessionInsertError),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<AuthError> for LoginError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => LoginError::InvalidCredentials(e),
+            _ => LoginError::AuthenticationError(e),
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| login_redirect(e.into()))?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    session.renew();
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| login_redirect(LoginError::SessionError(e)))?;
+
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Send the user back to the login form, explaining what went wrong
+/// through a flash message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other("/login"))
+}
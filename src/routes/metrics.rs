@@ -0,0 +1,24 @@
+use actix_web::HttpResponse;
+
+use crate::{metrics::METRICS, utils::e500};
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "meta",
+    responses(
+        (
+            status = 200,
+            description = "Metrics in the Prometheus text exposition format.",
+            body = String,
+            content_type = "text/plain"
+        )
+    )
+)]
+pub async fn metrics() -> Result<HttpResponse, actix_web::Error> {
+    METRICS.observe_pools();
+    let body = METRICS.render().map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
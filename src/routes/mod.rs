@@ -1,7 +1,21 @@
+mod admin;
 mod health_check;
+mod login;
+mod metrics;
+mod newsletters;
+mod openapi;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
+pub use login::*;
+pub use metrics::*;
+pub use newsletters::*;
+pub use openapi::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_resend::*;
+pub use subscriptions_unsubscribe::*;
@@ -0,0 +1,157 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::BasicAuthUser,
+    idempotency::{save_response, try_processing, IdempotencyError, IdempotencyKey, NextAction},
+    routes::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error(transparent)]
+    IdempotencyError(#[from] IdempotencyError),
+    #[error("Failed to store the newsletter issue.")]
+    InsertNewsletterIssueError(#[source] sqlx::Error),
+    #[error("Failed to enqueue the delivery tasks for the newsletter issue.")]
+    EnqueueDeliveryTasksError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::IdempotencyError(e) => e.status_code(),
+            PublishError::InsertNewsletterIssueError(_)
+            | PublishError::EnqueueDeliveryTasksError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    request_body = BodyData,
+    params(
+        (
+            "Idempotency-Key" = String,
+            Header,
+            description = "Retrying with the same key replays the first response instead of publishing twice."
+        )
+    ),
+    security(("basic_auth" = [])),
+    responses(
+        (status = 200, description = "The issue is queued for delivery to every confirmed subscriber."),
+        (status = 400, description = "Missing or invalid idempotency key.", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials."),
+        (status = 409, description = "A request with the same idempotency key is in progress.", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(user, body, pool),
+    fields(issue_title = %body.title, username = %user.username)
+)]
+pub async fn publish_newsletter(
+    user: BasicAuthUser,
+    // Extracted as a `Result` so that unauthenticated callers get a 401,
+    // whatever the state of their `Idempotency-Key` header.
+    idempotency_key: Result<IdempotencyKey, IdempotencyError>,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PublishError> {
+    let idempotency_key = idempotency_key?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, user.user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    .map_err(PublishError::InsertNewsletterIssueError)?;
+
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .map_err(PublishError::EnqueueDeliveryTasksError)?;
+
+    let response = HttpResponse::Ok().finish();
+    let response = save_response(transaction, &idempotency_key, user.user_id, response).await?;
+    Ok(response)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id,
+                title,
+                text_content,
+                html_content,
+                published_at
+            )
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id,
+                subscriber_email
+            )
+            SELECT $1, email
+            FROM subscriptions
+            WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
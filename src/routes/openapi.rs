@@ -0,0 +1,56 @@
+use actix_web::HttpResponse;
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+/// OpenAPI description of the endpoints meant for programmatic clients.
+/// The admin pages, behind a login form, are left out.
+///
+/// `openapi.json`, at the root of the repository, is a copy of the served
+/// document that client generators can use without a running instance.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter delivery service."),
+    paths(
+        crate::routes::health_check,
+        crate::routes::health_live,
+        crate::routes::health_ready,
+        crate::routes::subscribe,
+        crate::routes::confirm,
+        crate::routes::resend_confirmation,
+        crate::routes::unsubscribe_form,
+        crate::routes::unsubscribe,
+        crate::routes::publish_newsletter,
+        crate::routes::metrics,
+        openapi_spec,
+    ),
+    modifiers(&ApiDocModifier)
+)]
+pub struct ApiDoc;
+
+struct ApiDocModifier;
+
+impl Modify for ApiDocModifier {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        // utoipa fills it in from `Cargo.toml`, which does not declare one.
+        openapi.info.license = None;
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "basic_auth",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+            );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses((status = 200, description = "This document.", body = Object))
+)]
+pub async fn openapi_spec() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
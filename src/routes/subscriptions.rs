@@ -1,24 +1,34 @@
+use std::collections::BTreeMap;
+
 use actix_web::{
+    http::{
+        header::{AcceptLanguage, ContentType},
+        StatusCode,
+    },
     web::{self, Form},
-    HttpResponse,
+    HttpResponse, ResponseError,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 
 use uuid::Uuid;
 
 use crate::{
+    configuration::SubscriptionSettings,
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    email_client::{EmailError, EmailSender, EmailTemplates, TemplateError},
+    metrics::{begin_transaction, API_POOL},
 };
 
-use crate::startup::AplicationBaseUrl;
-
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Language of the emails, e.g. `es`. Takes precedence over the
+    /// `Accept-Language` header of the request.
+    pub locale: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -32,9 +42,33 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(
+        description = "Form submissions and JSON bodies are both accepted, based on `Content-Type`.",
+        content(
+            (FormData = "application/x-www-form-urlencoded"),
+            (FormData = "application/json"),
+        )
+    ),
+    responses(
+        (
+            status = 200,
+            description = "A confirmation link is on its way, unless the address was already confirmed.",
+            content((SubscribeResponse = "application/json"))
+        ),
+        (
+            status = 400,
+            description = "Invalid subscriber details.",
+            content((String = "text/plain"), (ValidationErrors = "application/json"))
+        ),
+    )
+)]
 #[tracing::instrument(
     name= "Adding a new Subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, accept_language, pool, email_templates, settings),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -43,76 +77,547 @@ impl TryFrom<FormData> for NewSubscriber {
 
 pub async fn subscribe(
     form: Form<FormData>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, SubscribeError> {
+    let preferences = preferred_locales(
+        form.locale.as_deref(),
+        accept_language.map(web::Header::into_inner),
+    );
+    let locale = email_templates.negotiate_locale(preferences.iter().map(String::as_str));
+
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+
+    register_subscriber(&new_subscriber, locale, &pool, &settings).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Same as `subscribe`, for clients posting `application/json`: invalid
+/// fields are reported all at once, as `{"errors": {"<field>": "<error>"}}`.
+#[tracing::instrument(
+    name = "Adding a new Subscriber through the JSON API",
+    skip(body, accept_language, pool, email_templates, settings),
+    fields(
+        subscriber_email = %body.email,
+        subscriber_name = %body.name
+    )
+)]
+pub async fn subscribe_json(
+    body: web::Json<FormData>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<AplicationBaseUrl>
-) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
-        Ok(form) => form,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+    email_templates: web::Data<EmailTemplates>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, SubscribeError> {
+    let preferences = preferred_locales(
+        body.locale.as_deref(),
+        accept_language.map(web::Header::into_inner),
+    );
+    let locale = email_templates.negotiate_locale(preferences.iter().map(String::as_str));
+
+    let new_subscriber = parse_fields(body.0).map_err(SubscribeError::InvalidFields)?;
+
+    register_subscriber(&new_subscriber, locale, &pool, &settings).await?;
+
+    // The body is the same whether the address was already on the list or not.
+    Ok(HttpResponse::Ok().json(SubscribeResponse { status: "ok" }))
+}
+
+/// Stores `new_subscriber`, unless already there, and queues a
+/// confirmation link for them.
+///
+/// The email goes out from `confirmation_email_worker`: waiting for the
+/// email API here would make new addresses measurably slower to answer
+/// than confirmed ones.
+async fn register_subscriber(
+    new_subscriber: &NewSubscriber,
+    locale: &str,
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<(), SubscribeError> {
+    let mut transaction = begin_transaction(API_POOL, pool)
+        .await
+        .map_err(SubscribeError::PoolError)?;
+
+    // Re-subscribing must look exactly like a first subscription from the
+    // outside, otherwise the endpoint leaks which addresses are on the list.
+    let subscriber_id = match get_or_insert_subscriber(&mut transaction, new_subscriber, locale)
+        .await?
+    {
+        StoredSubscriber::Existing(existing) if existing.status == "confirmed" => return Ok(()),
+        StoredSubscriber::Existing(existing) => {
+            // The row is locked: concurrent requests queue up here and see
+            // each other's tokens. Pending addresses share the cooldown of
+            // `/subscriptions/resend`, or this would be a way around it.
+            let last_sent_at = get_last_token_creation(&mut transaction, existing.id)
+                .await
+                .map_err(SubscribeError::GetExistingSubscriberError)?;
+            if last_sent_at.is_some_and(|sent_at| Utc::now() - sent_at < settings.resend_cooldown())
+            {
+                return Ok(());
+            }
+            // Unsubscribing deleted the old tokens: only the link sent below
+            // can confirm the address again.
+            if existing.status == "unsubscribed" {
+                reset_to_pending_confirmation(&mut transaction, existing.id)
+                    .await
+                    .map_err(SubscribeError::ResubscribeError)?;
+            }
+            existing.id
+        }
+        StoredSubscriber::Inserted(subscriber_id) => subscriber_id,
     };
 
-    match insert_subscriber(&pool, &new_subscriber).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    let subscription_token = match get_token_for_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(SubscribeError::GetExistingSubscriberError)?
+    {
+        Some(subscription_token) => {
+            mark_token_as_sent(&mut transaction, &subscription_token).await?;
+            subscription_token
+        }
+        None => {
+            let subscription_token = generate_subscription_token();
+            let expires_at = Utc::now() + settings.token_ttl();
+            store_token(
+                &mut transaction,
+                subscriber_id,
+                &subscription_token,
+                expires_at,
+            )
+            .await?;
+            subscription_token
+        }
     };
 
-    if send_confirmation_email(&email_client, new_subscriber, &base_url.0)
+    enqueue_confirmation_email(
+        &mut transaction,
+        &subscription_token,
+        new_subscriber.name.as_ref(),
+        locale,
+    )
+    .await
+    .map_err(SubscribeError::EnqueueConfirmationEmailError)?;
+
+    // Nothing is visible to other connections until all rows are in:
+    // the confirmation email only goes out for subscribers we actually stored.
+    transaction
+        .commit()
         .await
-        .is_err()
+        .map_err(SubscribeError::TransactionCommitError)?;
+
+    Ok(())
+}
+
+/// The locale picked on the form first, then the ones of the
+/// `Accept-Language` header, most preferred first.
+fn preferred_locales(
+    form_locale: Option<&str>,
+    accept_language: Option<AcceptLanguage>,
+) -> Vec<String> {
+    let accepted = accept_language
+        .map(|header| header.ranked())
+        .unwrap_or_default();
+    form_locale
+        .map(str::to_string)
+        .into_iter()
+        .chain(accepted.iter().map(ToString::to_string))
+        .collect()
+}
+
+pub type FieldErrors = BTreeMap<&'static str, String>;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct SubscribeResponse {
+    #[schema(example = "ok")]
+    pub status: &'static str,
+}
+
+/// Body of the `400 Bad Request` responses of the JSON API, keyed by field.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ValidationErrors {
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub errors: FieldErrors,
+}
+
+/// Unlike `TryFrom<FormData>`, keeps going after the first invalid field.
+fn parse_fields(data: FormData) -> Result<NewSubscriber, FieldErrors> {
+    match (
+        SubscriberName::parse(data.name),
+        SubscriberEmail::parse(data.email),
+    ) {
+        (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+        (name, email) => {
+            let mut errors = FieldErrors::new();
+            if let Err(e) = name {
+                errors.insert("name", e);
+            }
+            if let Err(e) = email {
+                errors.insert("email", e);
+            }
+            Err(errors)
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The subscriber details are not valid.")]
+    InvalidFields(FieldErrors),
+    #[error("Failed to acquire a Postgres connection from the pool.")]
+    PoolError(#[source] sqlx::Error),
+    #[error("Failed to look up the existing subscription for the provided email.")]
+    GetExistingSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to insert new subscriber in the database.")]
+    InsertSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to mark an unsubscribed address as pending confirmation.")]
+    ResubscribeError(#[source] sqlx::Error),
+    #[error("Failed to store the confirmation token for a new subscriber.")]
+    StoreTokenError(#[from] StoreTokenError),
+    #[error("Failed to queue the confirmation email.")]
+    EnqueueConfirmationEmailError(#[source] sqlx::Error),
+    #[error("Failed to commit SQL transaction to store a new subscriber.")]
+    TransactionCommitError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeError::ValidationError(_) | SubscribeError::InvalidFields(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            SubscribeError::PoolError(_)
+            | SubscribeError::GetExistingSubscriberError(_)
+            | SubscribeError::InsertSubscriberError(_)
+            | SubscribeError::ResubscribeError(_)
+            | SubscribeError::StoreTokenError(_)
+            | SubscribeError::EnqueueConfirmationEmailError(_)
+            | SubscribeError::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::InvalidFields(errors) => {
+                HttpResponse::build(self.status_code()).json(ValidationErrors {
+                    errors: errors.clone(),
+                })
+            }
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
+
+pub struct StoreTokenError(sqlx::Error);
+
+impl std::fmt::Display for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A database error was encountered while trying to store a subscription token."
+        )
+    }
+}
+
+impl std::fmt::Debug for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl std::error::Error for StoreTokenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Writes the error followed by every error in its `source` chain,
+/// so the whole story ends up in the logs emitted by `TracingLogger`.
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+/// Generate a random 25-characters-long case-sensitive token, used both to
+/// confirm a subscription and to unsubscribe.
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+enum StoredSubscriber {
+    Existing(ExistingSubscriber),
+    Inserted(Uuid),
+}
+
+/// Locks the row of `new_subscriber`, inserting it if it is not there yet.
+async fn get_or_insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    locale: &str,
+) -> Result<StoredSubscriber, SubscribeError> {
+    if let Some(existing) = get_existing_subscriber(transaction, &new_subscriber.email)
+        .await
+        .map_err(SubscribeError::GetExistingSubscriberError)?
+    {
+        return Ok(StoredSubscriber::Existing(existing));
+    }
+    if let Some(subscriber_id) = insert_subscriber(transaction, new_subscriber, locale)
+        .await
+        .map_err(SubscribeError::InsertSubscriberError)?
     {
-        return HttpResponse::InternalServerError().finish();
+        return Ok(StoredSubscriber::Inserted(subscriber_id));
     }
+    // A concurrent first subscription of the same address got there first:
+    // the insert waited for it to commit, so its row is visible now.
+    get_existing_subscriber(transaction, &new_subscriber.email)
+        .await
+        .and_then(|existing| existing.ok_or(sqlx::Error::RowNotFound))
+        .map(StoredSubscriber::Existing)
+        .map_err(SubscribeError::GetExistingSubscriberError)
+}
 
-    HttpResponse::Ok().finish()
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub name: String,
+    pub status: String,
+    pub locale: Option<String>,
 }
 
+#[tracing::instrument(
+    name = "Looking up an existing subscriber by email",
+    skip(email, transaction)
+)]
+pub async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    let existing = sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, name, status, locale FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(existing)
+}
+
+/// Only tokens that have not expired yet are worth sending again.
+#[tracing::instrument(
+    name = "Fetching the subscription token of a pending subscriber",
+    skip(transaction)
+)]
+pub async fn get_token_for_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            SELECT subscription_token
+            FROM subscription_tokens
+            WHERE subscriber_id = $1 AND expires_at > now()
+            ORDER BY created_at DESC
+            LIMIT 1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| r.subscription_token))
+}
+
+#[tracing::instrument(name = "Get the creation time of the last token", skip(transaction))]
+pub async fn get_last_token_creation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT max(created_at) AS last_created_at FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(result.last_created_at)
+}
+
+/// `created_at` doubles as the time the link was last sent: sending an
+/// existing token again restarts the cooldown.
+#[tracing::instrument(name = "Mark a subscription token as sent", skip_all)]
+async fn mark_token_as_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), StoreTokenError> {
+    sqlx::query!(
+        r#"UPDATE subscription_tokens SET created_at = now() WHERE subscription_token = $1"#,
+        subscription_token
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(StoreTokenError)?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Mark an unsubscribed address as pending", skip(transaction))]
+async fn reset_to_pending_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET status = 'pending_confirmation'
+            WHERE id = $1 AND status = 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Returns `None` if the email is already stored, instead of failing on
+/// the unique constraint.
 #[tracing::instrument(
     name = "Saving new subscriber details in database",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, transaction)
 )]
 pub async fn insert_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    locale: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token, locale)
+            VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
         "#,
-        &Uuid::new_v4(),
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        generate_subscription_token(),
+        locale
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), StoreTokenError> {
+    sqlx::query!(
+        r#"
+            INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        subscription_token,
+        subscriber_id,
+        Utc::now(),
+        expires_at
     )
-    .execute(pool)
+    .execute(&mut **transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query {:?}", e);
-        e
-    })?;
+    .map_err(StoreTokenError)?;
     Ok(())
 }
 
+/// Queues a confirmation email for `confirmation_email_worker`. A link
+/// already waiting in the queue is sent once.
+#[tracing::instrument(
+    name = "Queue a confirmation email",
+    skip(transaction, subscription_token, subscriber_name)
+)]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+    subscriber_name: &str,
+    locale: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO confirmation_email_queue (subscription_token, subscriber_name, locale)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (subscription_token) DO NOTHING
+        "#,
+        subscription_token,
+        subscriber_name,
+        locale
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum SendConfirmationError {
+    #[error("Failed to render the confirmation email.")]
+    RenderError(#[from] TemplateError),
+    #[error("Failed to deliver the confirmation email.")]
+    DeliveryError(#[from] EmailError),
+}
+
+impl std::fmt::Debug for SendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
 #[tracing::instrument(
     name = "Send confirmation email to new subscriber",
-    skip(email_client, new_subscriber)
+    skip(email_client, email_templates, recipient, subscription_token)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &str
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!("{}/subscriptions/confirm?subscription_token=mytoken", base_url);
-
-    let plain_body = format!(
-        "Welcome to out newsletter! \n Visit {} to confirm your subscription",
-        confirmation_link
+    email_client: &dyn EmailSender,
+    email_templates: &EmailTemplates,
+    recipient: &NewSubscriber,
+    locale: &str,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), SendConfirmationError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
     );
 
-    let html_body = format!("Welcome to out newsletter! <br /> Visit <a href=\"{}\">here</a> to confirm your subscription", confirmation_link);
+    let email = email_templates.confirmation(locale, &recipient.name, &confirmation_link)?;
 
     email_client
-        .send_email(new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email(&recipient.email, &email.subject, &email.html, &email.text)
+        .await?;
+    Ok(())
 }
@@ -1,11 +1,197 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+use crate::{
+    metrics::{begin_transaction, API_POOL},
+    routes::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
+    /// Token of the confirmation link sent by email.
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(_parameters))]
-pub async fn confirm(_parameters: web::Query<Parameters>) -> HttpResponse {
-    HttpResponse::Ok().finish()
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("The provided token has expired.")]
+    ExpiredToken,
+    #[error("Failed to retrieve the subscriber associated with the provided token.")]
+    GetSubscriberIdError(#[source] sqlx::Error),
+    #[error("Failed to acquire a Postgres connection from the pool.")]
+    PoolError(#[source] sqlx::Error),
+    #[error("Failed to mark the subscriber as confirmed.")]
+    ConfirmSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to delete the confirmation tokens of the subscriber.")]
+    DeleteSubscriptionTokensError(#[source] sqlx::Error),
+    #[error("Failed to commit SQL transaction to confirm a subscriber.")]
+    TransactionCommitError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken => StatusCode::GONE,
+            ConfirmError::GetSubscriberIdError(_)
+            | ConfirmError::PoolError(_)
+            | ConfirmError::ConfirmSubscriberError(_)
+            | ConfirmError::DeleteSubscriptionTokensError(_)
+            | ConfirmError::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // Subscribers land here from their inbox: give them a way out.
+            ConfirmError::ExpiredToken => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(EXPIRED_TOKEN_PAGE),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
+
+const EXPIRED_TOKEN_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired.</p>
+    <p>Enter your email address to receive a new one:</p>
+    <form action="/subscriptions/resend" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Resend confirmation email</button>
+    </form>
+</body>
+</html>"#;
+
+pub struct SubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Tokens are single use: they are deleted once the subscription is
+/// confirmed, and on unsubscribe.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscription is confirmed, or was no longer pending."),
+        (status = 401, description = "Unknown token.", body = String, content_type = "text/plain"),
+        (
+            status = 410,
+            description = "The token has expired: the page lets the subscriber ask for a new link.",
+            body = String,
+            content_type = "text/html"
+        ),
+    )
+)]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ConfirmError> {
+    let token = get_subscription_token(&pool, &parameters.subscription_token)
+        .await
+        .map_err(ConfirmError::GetSubscriberIdError)?
+        .ok_or(ConfirmError::UnknownToken)?;
+
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+
+    let mut transaction = begin_transaction(API_POOL, &pool)
+        .await
+        .map_err(ConfirmError::PoolError)?;
+
+    confirm_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .map_err(ConfirmError::ConfirmSubscriberError)?;
+
+    delete_subscription_tokens(&mut transaction, token.subscriber_id)
+        .await
+        .map_err(ConfirmError::DeleteSubscriptionTokensError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ConfirmError::TransactionCommitError)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Only pending subscribers are confirmed: an old link must not bring
+/// back an address that has unsubscribed, and is a no-op otherwise.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip_all)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET status = 'confirmed'
+            WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete the confirmation tokens of a subscriber", skip_all)]
+async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, pool))]
+pub async fn get_subscription_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+            SELECT subscriber_id, expires_at
+            FROM subscription_tokens
+            WHERE subscription_token = $1
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(result)
 }
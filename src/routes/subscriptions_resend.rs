@@ -0,0 +1,149 @@
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::SubscriptionSettings,
+    domain::SubscriberEmail,
+    email_client::EmailTemplates,
+    metrics::{begin_transaction, API_POOL},
+    routes::{
+        enqueue_confirmation_email, error_chain_fmt, generate_subscription_token,
+        get_existing_subscriber, get_last_token_creation, store_token, StoreTokenError,
+    },
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct ResendFormData {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ResendError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Failed to acquire a Postgres connection from the pool.")]
+    PoolError(#[source] sqlx::Error),
+    #[error("Failed to look up the existing subscription for the provided email.")]
+    GetExistingSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to store the new confirmation token.")]
+    StoreTokenError(#[from] StoreTokenError),
+    #[error("Failed to queue the confirmation email.")]
+    EnqueueConfirmationEmailError(#[source] sqlx::Error),
+    #[error("Failed to commit SQL transaction to store a new confirmation token.")]
+    TransactionCommitError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for ResendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResendError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ResendError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ResendError::PoolError(_)
+            | ResendError::GetExistingSubscriberError(_)
+            | ResendError::StoreTokenError(_)
+            | ResendError::EnqueueConfirmationEmailError(_)
+            | ResendError::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Sends a fresh confirmation link to a pending subscriber.
+///
+/// Unknown and already confirmed addresses get the same answer as pending
+/// ones, without any email, so the endpoint does not leak who is on the list.
+/// So do pending ones sent a link moments ago: nothing is sent until the
+/// cooldown is over. The link itself is queued for
+/// `confirmation_email_worker`, so every branch answers equally fast.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/resend",
+    tag = "subscriptions",
+    request_body(content = ResendFormData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "A new link is sent if the address is pending.", body = String, content_type = "text/html"),
+        (status = 400, description = "Invalid email address.", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_templates, settings),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, ResendError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(ResendError::ValidationError)?;
+
+    let mut transaction = begin_transaction(API_POOL, &pool)
+        .await
+        .map_err(ResendError::PoolError)?;
+
+    let subscriber = get_existing_subscriber(&mut transaction, &email)
+        .await
+        .map_err(ResendError::GetExistingSubscriberError)?;
+    let Some(subscriber) = subscriber.filter(|s| s.status == "pending_confirmation") else {
+        return Ok(resend_response());
+    };
+
+    // The subscriber row is locked: concurrent resends queue up here and
+    // see each other's tokens.
+    let last_sent_at = get_last_token_creation(&mut transaction, subscriber.id)
+        .await
+        .map_err(ResendError::GetExistingSubscriberError)?;
+    if last_sent_at.is_some_and(|sent_at| Utc::now() - sent_at < settings.resend_cooldown()) {
+        return Ok(resend_response());
+    }
+
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber.id,
+        &subscription_token,
+        Utc::now() + settings.token_ttl(),
+    )
+    .await?;
+
+    let locale = email_templates.negotiate_locale(subscriber.locale.as_deref());
+    enqueue_confirmation_email(
+        &mut transaction,
+        &subscription_token,
+        &subscriber.name,
+        locale,
+    )
+    .await
+    .map_err(ResendError::EnqueueConfirmationEmailError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ResendError::TransactionCommitError)?;
+
+    Ok(resend_response())
+}
+
+fn resend_response() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation email</title>
+</head>
+<body>
+    <p>If this address is waiting for confirmation, a new link is on its way.</p>
+</body>
+</html>"#,
+    )
+}
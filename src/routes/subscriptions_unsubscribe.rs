@@ -0,0 +1,210 @@
+use actix_web::{http::header::ContentType, http::StatusCode, web, HttpResponse, ResponseError};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    metrics::{begin_transaction, API_POOL},
+    routes::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeParameters {
+    /// Token of the `List-Unsubscribe` link of the newsletter issues.
+    unsubscribe_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("Failed to acquire a Postgres connection from the pool.")]
+    PoolError(#[source] sqlx::Error),
+    #[error("Failed to retrieve the subscriber associated with the provided token.")]
+    GetSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to mark the subscriber as unsubscribed.")]
+    UnsubscribeSubscriberError(#[source] sqlx::Error),
+    #[error("Failed to drop the pending deliveries to the subscriber.")]
+    DeletePendingDeliveriesError(#[source] sqlx::Error),
+    #[error("Failed to delete the confirmation tokens of the subscriber.")]
+    DeleteSubscriptionTokensError(#[source] sqlx::Error),
+    #[error("Failed to commit SQL transaction to unsubscribe a subscriber.")]
+    TransactionCommitError(#[source] sqlx::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnknownToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::PoolError(_)
+            | UnsubscribeError::GetSubscriberError(_)
+            | UnsubscribeError::UnsubscribeSubscriberError(_)
+            | UnsubscribeError::DeletePendingDeliveriesError(_)
+            | UnsubscribeError::DeleteSubscriptionTokensError(_)
+            | UnsubscribeError::TransactionCommitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Landing page of the `List-Unsubscribe` link.
+///
+/// It does not unsubscribe anyone by itself: link scanners and mail
+/// clients prefetch GET links, so the change needs an explicit POST.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(UnsubscribeParameters),
+    responses(
+        (status = 200, description = "A page asking for confirmation.", body = String, content_type = "text/html"),
+        (status = 401, description = "Unknown token.", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let exists = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        parameters.unsubscribe_token,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(UnsubscribeError::GetSubscriberError)?
+    .is_some();
+    if !exists {
+        return Err(UnsubscribeError::UnknownToken);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?unsubscribe_token={}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&parameters.unsubscribe_token)
+        )))
+}
+
+/// RFC 8058 one-click unsubscribe, also used by the landing page form.
+/// The `List-Unsubscribe=One-Click` body carries no information: the
+/// token in the query string is all we need.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(UnsubscribeParameters),
+    responses(
+        (status = 200, description = "The subscriber is unsubscribed.", body = String, content_type = "text/html"),
+        (status = 401, description = "Unknown token.", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = begin_transaction(API_POOL, &pool)
+        .await
+        .map_err(UnsubscribeError::PoolError)?;
+
+    let email = unsubscribe_subscriber(&mut transaction, &parameters.unsubscribe_token)
+        .await
+        .map_err(UnsubscribeError::UnsubscribeSubscriberError)?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+
+    delete_pending_deliveries(&mut transaction, &email)
+        .await
+        .map_err(UnsubscribeError::DeletePendingDeliveriesError)?;
+
+    // An old confirmation link must not subscribe the address again.
+    delete_subscription_tokens(&mut transaction, &email)
+        .await
+        .map_err(UnsubscribeError::DeleteSubscriptionTokensError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(UnsubscribeError::TransactionCommitError)?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive further issues.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/// Returns the email of the subscriber, if the token belongs to one.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip_all)]
+pub async fn unsubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    unsubscribe_token: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET status = 'unsubscribed'
+            WHERE unsubscribe_token = $1
+            RETURNING email
+        "#,
+        unsubscribe_token,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| r.email))
+}
+
+#[tracing::instrument(name = "Drop pending deliveries to an unsubscribed address", skip_all)]
+async fn delete_pending_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        email,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete the tokens of an unsubscribed address", skip_all)]
+async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE email = $1)
+        "#,
+        email,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+
+use crate::session_store::generate_session_key;
+
+type SessionState = HashMap<String, String>;
+
+/// Process-local session store, meant for tests.
+/// Clones share the same sessions, so it can be handed to every worker.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionState, Instant)>>>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn expires_at(ttl: &Duration) -> Instant {
+    Instant::now() + std::time::Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.sessions.lock().unwrap().insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(session_key.as_ref()) {
+            Some(entry) if entry.1 > Instant::now() => {
+                *entry = (session_state, expires_at(ttl));
+                Ok(session_key)
+            }
+            _ => {
+                sessions.remove(session_key.as_ref());
+                let session_key = generate_session_key();
+                sessions.insert(
+                    session_key.as_ref().to_owned(),
+                    (session_state, expires_at(ttl)),
+                );
+                Ok(session_key)
+            }
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(entry) = self.sessions.lock().unwrap().get_mut(session_key.as_ref()) {
+            entry.1 = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+
+    use super::InMemorySessionStore;
+
+    fn state() -> HashMap<String, String> {
+        HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+    }
+
+    #[tokio::test]
+    async fn a_saved_session_can_be_loaded_back() {
+        let store = InMemorySessionStore::new();
+
+        let key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), Some(state()));
+    }
+
+    #[tokio::test]
+    async fn an_expired_session_is_not_loaded() {
+        let store = InMemorySessionStore::new();
+
+        let key = store.save(state(), &Duration::ZERO).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn a_deleted_session_is_not_loaded() {
+        let store = InMemorySessionStore::new();
+        let key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+        store.delete(&key).await.unwrap();
+
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn updating_an_expired_session_issues_a_new_key() {
+        let store = InMemorySessionStore::new();
+        let key = store.save(state(), &Duration::ZERO).await.unwrap();
+        let old_key = key.as_ref().to_owned();
+
+        let new_key = store
+            .update(key, state(), &Duration::minutes(5))
+            .await
+            .unwrap();
+
+        assert_ne!(new_key.as_ref(), old_key);
+        assert_eq!(store.load(&new_key).await.unwrap(), Some(state()));
+    }
+}
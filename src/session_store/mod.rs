@@ -0,0 +1,20 @@
+mod in_memory;
+mod postgres;
+
+pub use in_memory::InMemorySessionStore;
+pub use postgres::PostgresSessionStore;
+
+use actix_session::storage::SessionKey;
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Session keys end up in cookies: 64 alphanumeric characters give us
+/// plenty of entropy while staying far below the 4064 bytes cookie limit.
+fn generate_session_key() -> SessionKey {
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect();
+    key.try_into()
+        .expect("A 64 characters long key is a valid session key")
+}
@@ -0,0 +1,136 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::watch;
+
+/// How long the tasks get past the grace period before the process exits.
+///
+/// The API only stops its server once the grace period is over, and each
+/// task closes its database pool after that: exiting right at the end of
+/// the grace period would skip both steps when they matter most.
+pub const SHUTDOWN_MARGIN: Duration = Duration::from_secs(5);
+
+/// Tells the API and the background workers to wind down.
+///
+/// Each task holds a `Shutdown`, from `subscribe`, and stops taking new
+/// work once `trigger` is called: the work in progress is left to finish.
+pub struct ShutdownController {
+    sender: watch::Sender<bool>,
+}
+
+impl ShutdownController {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown(self.sender.subscribe())
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+impl Default for ShutdownController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the shutdown is triggered. Never resolves if the
+    /// controller is dropped without triggering it.
+    pub async fn requested(&mut self) {
+        if self.0.wait_for(|requested| *requested).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Counts the requests being handled, for the API to wait for them before
+/// stopping.
+///
+/// actix-web has a graceful shutdown of its own, but its workers can drop
+/// their connections as soon as the server stops accepting new ones.
+#[derive(Clone, Default)]
+pub struct InFlightRequests(Arc<AtomicUsize>);
+
+impl InFlightRequests {
+    pub async fn track<F: Future>(&self, request: F) -> F::Output {
+        let _guard = InFlightGuard::new(self.0.clone());
+        request.await
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once no request is being handled.
+    pub async fn drained(&self) {
+        while self.count() > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// Decrements the count when the request completes, or is dropped.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+    fn new(count: Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves on SIGTERM, sent by the platform on deploys, or on Ctrl+C.
+///
+/// Never resolves if the signal handlers cannot be installed: the process
+/// then runs until killed, as it did before.
+pub async fn shutdown_signal() {
+    if let Err(e) = wait_for_signal().await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to listen for shutdown signals"
+        );
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn wait_for_signal() -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            _ = sigterm.recv() => Ok(()),
+            result = tokio::signal::ctrl_c() => result,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
@@ -1,94 +1,240 @@
 use crate::routes::confirm;
-use std::net::TcpListener;
+use std::{net::TcpListener, time::Duration};
 
+use actix_session::{config::CookieContentSecurity, SessionMiddleware};
 use actix_web::{
-    dev::Server,
+    cookie::Key,
+    dev::{Server, Service},
+    error::InternalError,
+    guard::{self, GuardContext},
+    http::header::ContentType,
+    middleware::from_fn,
     web::{self, Data},
-    App, HttpServer,
+    App, HttpResponse, HttpServer,
 };
+use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
-    routes::{health_check, subscribe},
+    authentication::{reject_anonymous_users, set_admin_password_hash},
+    configuration::{DatabaseSettings, Settings, SubscriptionSettings},
+    email_client::EmailTemplates,
+    metrics::{record_http_metrics, API_POOL, METRICS},
+    routes::{
+        admin_dashboard, change_password, change_password_form, health_check, health_live,
+        health_ready, log_out, login, login_form, metrics, openapi_spec, publish_newsletter,
+        resend_confirmation, subscribe, subscribe_json, unsubscribe, unsubscribe_form,
+        EmailApiProbe,
+    },
+    session_store::PostgresSessionStore,
+    shutdown::{InFlightRequests, Shutdown},
+    telemetry::AppRootSpanBuilder,
 };
 
 pub struct Application {
     port: u16,
     server: Server,
+    connection_pool: PgPool,
+    in_flight_requests: InFlightRequests,
+    shutdown_grace_period: Duration,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-
-        let sender_email = configuration
+        METRICS.register_pool(API_POOL, &connection_pool);
+        if let Some(password_hash) = &configuration.application.admin_password_hash {
+            set_admin_password_hash(password_hash, &connection_pool)
+                .await
+                .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+        }
+
+        let email_templates = configuration
             .email_client
-            .sender()
-            .expect("Invalid sender email addres");
-
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
-
+            .templates(&configuration.application.default_locale)
+            .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+        let email_api_probe = configuration.health.check_email_api.then(|| {
+            EmailApiProbe::new(
+                configuration.email_client.base_url.clone(),
+                configuration.health.timeout(),
+            )
+        });
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
 
+        let shutdown_grace_period = configuration.application.shutdown_grace_period();
         let listener = TcpListener::bind(&address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(listener, connection_pool, email_client, configuration.application.base_url).await?;
-
-        Ok(Self { port, server })
+        let in_flight_requests = InFlightRequests::default();
+        let server = run(
+            listener,
+            connection_pool.clone(),
+            email_templates,
+            configuration.application.hmac_secret,
+            configuration.subscriptions,
+            email_api_probe,
+            in_flight_requests.clone(),
+        )
+        .await?;
+
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            in_flight_requests,
+            shutdown_grace_period,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// Once `shutdown` is requested, stops accepting connections and waits
+    /// for the requests in flight, up to the grace period, before closing
+    /// the database pool.
+    pub async fn run_until_stopped(self, mut shutdown: Shutdown) -> Result<(), std::io::Error> {
+        let mut server = self.server;
+        let handle = server.handle();
+        let result = tokio::select! {
+            result = &mut server => result,
+            _ = shutdown.requested() => {
+                tracing::info!("Stopping the API");
+                let stop = async {
+                    handle.pause().await;
+                    let drained = tokio::time::timeout(
+                        self.shutdown_grace_period,
+                        self.in_flight_requests.drained(),
+                    )
+                    .await;
+                    if drained.is_err() {
+                        tracing::warn!(
+                            n_requests = self.in_flight_requests.count(),
+                            "Dropping the requests still in flight after the grace period"
+                        );
+                    }
+                    // Only idle keep-alive connections are left: no need to
+                    // wait for clients to close them.
+                    handle.stop(false).await;
+                };
+                // The server only handles the commands while it is polled.
+                let (_, result) = tokio::join!(stop, server);
+                result
+            }
+        };
+        self.connection_pool.close().await;
+        result
     }
 }
 
-pub struct AplicationBaseUrl(pub String);
-
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String
+    email_templates: EmailTemplates,
+    hmac_secret: Secret<String>,
+    subscription_settings: SubscriptionSettings,
+    email_api_probe: Option<EmailApiProbe>,
+    in_flight_requests: InFlightRequests,
 ) -> Result<Server, std::io::Error> {
-    let base_url = Data::new(AplicationBaseUrl(base_url));
+    let email_templates = Data::new(email_templates);
 
-    let email_client = Data::new(email_client);
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let session_store = PostgresSessionStore::new(db_pool.clone());
 
     let connection = web::Data::new(db_pool);
+    let subscription_settings = Data::new(subscription_settings);
+    let email_api_probe = Data::new(email_api_probe);
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(message_framework.clone())
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .build(),
+            )
+            .wrap(from_fn(record_http_metrics))
+            .wrap_fn({
+                let in_flight_requests = in_flight_requests.clone();
+                move |req, srv| {
+                    let in_flight_requests = in_flight_requests.clone();
+                    let response = srv.call(req);
+                    async move { in_flight_requests.track(response).await }
+                }
+            })
+            .wrap(TracingLogger::<AppRootSpanBuilder>::new())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            .route("/openapi.json", web::get().to(openapi_spec))
+            .route("/metrics", web::get().to(metrics))
+            .service(
+                web::resource("/subscriptions")
+                    .app_data(subscription_json_config())
+                    .route(
+                        web::post()
+                            .guard(guard::fn_guard(is_json_request))
+                            .to(subscribe_json),
+                    )
+                    .route(web::post().to(subscribe)),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/resend", web::post().to(resend_confirmation))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out)),
+            )
             .app_data(connection.clone())
-            .app_data(email_client.clone())
-            .app_data(base_url.clone())
+            .app_data(email_templates.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(email_api_probe.clone())
     })
+    // Signals are handled by `main`, which also stops the background workers.
+    .disable_signals()
     .listen(listener)?
     .run();
     //No .awaithere!
     Ok(server)
 }
 
+/// `POST /subscriptions` serves both HTML forms and JSON clients, based on
+/// the `Content-Type` of the request.
+fn is_json_request(ctx: &GuardContext) -> bool {
+    ctx.header::<ContentType>()
+        .is_some_and(|ContentType(mime)| {
+            mime.type_() == "application"
+                && (mime.subtype() == "json" || mime.suffix().is_some_and(|s| s == "json"))
+        })
+}
+
+/// JSON clients get malformed bodies reported like the other validation
+/// errors of the endpoint.
+fn subscription_json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _| {
+        let response = HttpResponse::BadRequest()
+            .json(serde_json::json!({ "errors": { "body": err.to_string() } }));
+        InternalError::from_response(err, response).into()
+    })
+}
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -0,0 +1,78 @@
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{Settings, SubscriptionSettings},
+    metrics::{METRICS, SUBSCRIPTION_CLEANUP_POOL},
+    shutdown::Shutdown,
+    startup::get_connection_pool,
+};
+
+pub async fn run_cleanup_until_stopped(
+    configuration: Settings,
+    shutdown: Shutdown,
+) -> Result<(), std::io::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    METRICS.register_pool(SUBSCRIPTION_CLEANUP_POOL, &connection_pool);
+    cleanup_loop(connection_pool, configuration.subscriptions, shutdown).await
+}
+
+async fn cleanup_loop(
+    pool: PgPool,
+    settings: SubscriptionSettings,
+    mut shutdown: Shutdown,
+) -> Result<(), std::io::Error> {
+    while !shutdown.is_requested() {
+        // Failures are logged by `delete_stale_pending_subscribers`:
+        // we'll try again at the next tick.
+        let _ = delete_stale_pending_subscribers(&pool, settings.pending_retention()).await;
+        tokio::select! {
+            _ = tokio::time::sleep(settings.cleanup_interval()) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+    pool.close().await;
+    Ok(())
+}
+
+/// Deletes the subscribers that never confirmed, once their last
+/// confirmation link has been expired for longer than `retention`,
+/// together with their tokens.
+/// Returns how many subscribers were deleted.
+#[tracing::instrument(skip(pool), fields(n_deleted = tracing::field::Empty), err)]
+pub async fn delete_stale_pending_subscribers(
+    pool: &PgPool,
+    retention: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - retention;
+    // Tokens and subscribers go in a single statement: foreign keys are
+    // only checked once both deletes have run.
+    let n_deleted = sqlx::query!(
+        r#"
+            WITH stale AS (
+                SELECT id
+                FROM subscriptions s
+                WHERE
+                    s.status = 'pending_confirmation' AND
+                    s.subscribed_at < $1 AND
+                    NOT EXISTS (
+                        SELECT 1
+                        FROM subscription_tokens t
+                        WHERE t.subscriber_id = s.id AND t.expires_at > $1
+                    )
+            ), deleted_tokens AS (
+                DELETE FROM subscription_tokens
+                WHERE subscriber_id IN (SELECT id FROM stale)
+            )
+            DELETE FROM subscriptions
+            WHERE id IN (SELECT id FROM stale)
+        "#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    tracing::Span::current().record("n_deleted", n_deleted);
+    Ok(n_deleted)
+}
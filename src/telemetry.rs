@@ -1,13 +1,33 @@
-use tracing::{Subscriber, dispatcher::set_global_default};
+use std::time::Duration;
+
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+};
+use opentelemetry_otlp::{ExporterBuildError, Protocol, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, Tracer},
+    Resource,
+};
+use tokio::task::JoinHandle;
+use tracing::{dispatcher::set_global_default, Span, Subscriber};
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{EnvFilter, Registry, fmt::MakeWriter, layer::SubscriberExt};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+
+use crate::configuration::OtlpSettings;
 
 // logs config
+/// Spans also go to `tracer`, when there is one: see `init_tracer_provider`.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -19,9 +39,280 @@ where
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formating_layer)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Exports spans to an OpenTelemetry collector over OTLP/HTTP, if enabled.
+///
+/// Also installs the W3C Trace Context propagator, used to join the traces
+/// of our callers and to pass ours on to the services we call.
+/// The provider must be shut down before exiting, to flush the last spans.
+pub fn init_tracer_provider(
+    service_name: &str,
+    settings: &OtlpSettings,
+) -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    if !settings.enabled {
+        return Ok(None);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(&settings.endpoint)
+        .with_timeout(Duration::from_millis(settings.timeout_milliseconds))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber.into()).expect("Failed to set subscriber");
 }
+
+/// Like `tokio::task::spawn_blocking`, but the closure runs inside the
+/// caller's span so its logs stay attached to the request that needed it.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+/// Root span for every incoming request: the default fields from
+/// `tracing-actix-web`, plus the identity of the caller once authenticated.
+pub struct AppRootSpanBuilder;
+
+impl RootSpanBuilder for AppRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let span = root_span!(
+            request,
+            username = tracing::field::Empty,
+            user_id = tracing::field::Empty
+        );
+        // Requests coming with a `traceparent` header join the caller's trace.
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&RequestHeaders(request.headers()))
+        });
+        let _ = span.set_parent(parent);
+        span
+    }
+
+    fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, actix_web::Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// The trace context of the current span, as headers for an outgoing
+/// request, so that the services we call can attach their spans to it.
+/// Empty when spans are not exported.
+pub fn trace_context_headers() -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut OutgoingHeaders(&mut headers))
+    });
+    headers
+}
+
+struct RequestHeaders<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct OutgoingHeaders<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for OutgoingHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use secrecy::Secret;
+    use tracing::Instrument;
+    use tracing_actix_web::TracingLogger;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{get_subscriber, init_tracer_provider, AppRootSpanBuilder};
+    use crate::{
+        configuration::OtlpSettings,
+        domain::SubscriberEmail,
+        email_client::{EmailClient, EmailSender, RetryPolicy},
+    };
+
+    /// Stands in for an OpenTelemetry collector, accepting every OTLP/HTTP export.
+    async fn start_collector() -> MockServer {
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&collector)
+            .await;
+        collector
+    }
+
+    fn tracer_provider(collector: &MockServer) -> SdkTracerProvider {
+        let settings = OtlpSettings {
+            enabled: true,
+            endpoint: format!("{}/v1/traces", collector.uri()),
+            timeout_milliseconds: 2000,
+        };
+        init_tracer_provider("zero2prod-test", &settings)
+            .expect("Failed to build the exporter")
+            .expect("The exporter is enabled")
+    }
+
+    fn subscriber(provider: &SdkTracerProvider) -> impl tracing::Subscriber + Send + Sync {
+        get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            Some(provider.tracer("test")),
+        )
+    }
+
+    /// Every span received by the collector, in the OTLP JSON encoding.
+    async fn exported_spans(collector: &MockServer) -> Vec<serde_json::Value> {
+        let mut spans = Vec::new();
+        for request in collector.received_requests().await.unwrap() {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            for resource_spans in body["resourceSpans"].as_array().unwrap() {
+                for scope_spans in resource_spans["scopeSpans"].as_array().unwrap() {
+                    spans.extend(scope_spans["spans"].as_array().unwrap().iter().cloned());
+                }
+            }
+        }
+        spans
+    }
+
+    #[tokio::test]
+    async fn disabled_exporter_builds_no_provider() {
+        let settings = OtlpSettings {
+            enabled: false,
+            endpoint: "http://127.0.0.1:4318/v1/traces".into(),
+            timeout_milliseconds: 2000,
+        };
+
+        assert!(init_tracer_provider("zero2prod-test", &settings)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn spans_are_exported_to_the_collector() {
+        let collector = start_collector().await;
+        let provider = tracer_provider(&collector);
+
+        tracing::subscriber::with_default(subscriber(&provider), || {
+            tracing::info_span!("Exported span").in_scope(|| {});
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exported_spans(&collector).await;
+        assert!(spans.iter().any(|span| span["name"] == "Exported span"));
+    }
+
+    #[tokio::test]
+    async fn requests_with_a_traceparent_join_the_upstream_trace() {
+        let collector = start_collector().await;
+        let provider = tracer_provider(&collector);
+        let app = init_service(
+            App::new()
+                .wrap(TracingLogger::<AppRootSpanBuilder>::new())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let guard = tracing::subscriber::set_default(subscriber(&provider));
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ))
+            .to_request();
+        call_service(&app, request).await;
+        drop(guard);
+        provider.force_flush().unwrap();
+
+        let spans = exported_spans(&collector).await;
+        assert!(spans.iter().any(|span| {
+            span["traceId"] == "0af7651916cd43dd8448eb211c80319c"
+                && span["parentSpanId"] == "b7ad6b7169203331"
+        }));
+    }
+
+    #[tokio::test]
+    async fn email_client_requests_carry_the_trace_context() {
+        let collector = start_collector().await;
+        let provider = tracer_provider(&collector);
+        let email_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&email_server)
+            .await;
+        let email_client = EmailClient::new(
+            email_server.uri(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Secret::new("token".into()),
+            std::time::Duration::from_secs(2),
+            RetryPolicy {
+                max_attempts: 1,
+                base_delay: std::time::Duration::from_millis(10),
+                max_delay: std::time::Duration::from_millis(10),
+                jitter: false,
+            },
+        );
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+
+        let guard = tracing::subscriber::set_default(subscriber(&provider));
+        let span = tracing::info_span!("Send an email");
+        let trace_id = span.context().span().span_context().trace_id().to_string();
+        email_client
+            .send_email(&recipient, "Subject", "<p>Body</p>", "Body")
+            .instrument(span)
+            .await
+            .unwrap();
+        drop(guard);
+
+        let requests = email_server.received_requests().await.unwrap();
+        let traceparent = requests[0].headers.get(&"traceparent".into()).unwrap();
+        assert!(traceparent.as_str().starts_with(&format!("00-{trace_id}-")));
+    }
+}
@@ -0,0 +1,15 @@
+use actix_web::{http::header::LOCATION, HttpResponse};
+
+/// Wrap an unexpected failure into a 500, keeping its error chain for the logs.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
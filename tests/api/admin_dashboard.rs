@@ -0,0 +1,44 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+
+    let response = app.login_as_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_deletes_the_stored_session() {
+    let app = spawn_app().await;
+
+    app.login_as_test_user().await;
+    app.post_logout().await;
+
+    let remaining = sqlx::query!("SELECT COUNT(*) AS count FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count stored sessions.");
+    assert_eq!(remaining.count, Some(0));
+}
@@ -0,0 +1,136 @@
+use secrecy::Secret;
+use uuid::Uuid;
+use zero2prod::{
+    authentication::{
+        change_password, set_admin_password_hash, validate_credentials, AuthError, Credentials,
+    },
+    configuration::get_configuration,
+    startup::Application,
+};
+
+use crate::helpers::{hash_password, spawn_app};
+
+#[tokio::test]
+async fn valid_credentials_resolve_to_the_user_id() {
+    let app = spawn_app().await;
+
+    let credentials = Credentials {
+        username: app.test_user.username.clone(),
+        password: Secret::new(app.test_user.password.clone()),
+    };
+
+    let user_id = validate_credentials(credentials, &app.db_pool)
+        .await
+        .expect("Valid credentials were rejected");
+
+    assert_eq!(user_id, app.test_user.user_id);
+}
+
+#[tokio::test]
+async fn an_invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let credentials = Credentials {
+        username: app.test_user.username.clone(),
+        password: Secret::new("definitely-not-the-password".into()),
+    };
+
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+}
+
+#[tokio::test]
+async fn an_unknown_username_is_rejected() {
+    let app = spawn_app().await;
+
+    let credentials = Credentials {
+        username: "nobody".into(),
+        password: Secret::new(app.test_user.password.clone()),
+    };
+
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+}
+
+#[tokio::test]
+async fn the_seeded_admin_has_no_usable_password() {
+    let app = spawn_app().await;
+
+    let credentials = Credentials {
+        username: "admin".into(),
+        password: Secret::new("everythinghastostartsomewhere".into()),
+    };
+
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+}
+
+#[tokio::test]
+async fn the_admin_password_can_be_set_from_a_hash() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+    let password_hash = hash_password(&password);
+
+    set_admin_password_hash(&Secret::new(password_hash), &app.db_pool)
+        .await
+        .unwrap();
+
+    let credentials = Credentials {
+        username: "admin".into(),
+        password: Secret::new(password),
+    };
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+    assert!(outcome.is_ok());
+}
+
+#[tokio::test]
+async fn an_invalid_admin_password_hash_is_rejected() {
+    let app = spawn_app().await;
+
+    let outcome =
+        set_admin_password_hash(&Secret::new("not-a-phc-string".into()), &app.db_pool).await;
+
+    assert!(matches!(outcome, Err(AuthError::InvalidNewHash(_))));
+}
+
+#[tokio::test]
+async fn the_configured_admin_password_hash_does_not_override_a_changed_password() {
+    let app = spawn_app().await;
+    let initial_password_hash = Secret::new(hash_password("the-initial-password"));
+    set_admin_password_hash(&initial_password_hash, &app.db_pool)
+        .await
+        .unwrap();
+    let admin_id = validate_credentials(
+        Credentials {
+            username: "admin".into(),
+            password: Secret::new("the-initial-password".into()),
+        },
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+    change_password(
+        admin_id,
+        Secret::new("a-password-of-their-own".into()),
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    // A restart, with the same configuration as the first deployment.
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.database_name = app.database_name.clone();
+    configuration.application.port = 0;
+    configuration.application.admin_password_hash = Some(initial_password_hash);
+    Application::build(configuration).await.unwrap();
+
+    let credentials = Credentials {
+        username: "admin".into(),
+        password: Secret::new("a-password-of-their-own".into()),
+    };
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+    assert!(outcome.is_ok());
+}
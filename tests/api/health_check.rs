@@ -1,3 +1,10 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
 use crate::helpers::spawn_app;
 
 #[tokio::test]
@@ -7,7 +14,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed execute request");
@@ -15,3 +22,73 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_works() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", &app.address))
+        .await
+        .expect("Failed execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_every_check() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "ready");
+    for check in ["database", "migrations", "email_api"] {
+        assert_eq!(report["checks"][check]["status"], "up", "{} is down", check);
+        assert!(report["checks"][check]["latency_ms"].is_number());
+    }
+}
+
+#[tokio::test]
+async fn readiness_fails_if_the_database_is_behind_the_migrations() {
+    let app = spawn_app().await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed execute request");
+
+    assert_eq!(response.status().as_u16(), 503);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "unavailable");
+    assert_eq!(report["checks"]["migrations"]["status"], "down");
+    assert_eq!(report["checks"]["database"]["status"], "up");
+}
+
+#[tokio::test]
+async fn an_unreachable_email_api_does_not_make_the_instance_unavailable() {
+    let app = spawn_app().await;
+    // Slower than the timeout of the probe.
+    Mock::given(method("GET"))
+        .and(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(10)))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["status"], "ready");
+    assert_eq!(report["checks"]["email_api"]["status"], "down");
+    assert_eq!(report["checks"]["email_api"]["critical"], false);
+}
@@ -1,12 +1,18 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
-use reqwest::Request;
 use secrecy::ExposeSecret;
 use sqlx::Executor;
 use sqlx::{Connection, PgConnection, PgPool};
 use std::io::{sink, stdout};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{MockServer, ResponseTemplate};
+use zero2prod::configuration::EmailBackend;
 use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::confirmation_email_worker::try_send_confirmation_email;
+use zero2prod::email_client::{EmailSender, EmailTemplates};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::shutdown::ShutdownController;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -15,17 +21,37 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     let subscriber_name = "test".to_string();
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, stdout);
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, stdout, None);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, sink);
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, sink, None);
         init_subscriber(subscriber);
     }
 });
 
+/// Replies to Postmark's `/email/batch` with a successful result for every message.
+pub struct PostmarkBatchResponder;
+
+impl wiremock::Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "To": message["To"],
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
-    pub plain_text: reqwest::Url
+    pub plain_text: reqwest::Url,
 }
 
 pub struct TestApp {
@@ -34,28 +60,258 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub port: u16,
     pub database_name: String,
-    pub db_configuration: DatabaseSettings
+    pub db_configuration: DatabaseSettings,
+    pub email_client: Arc<dyn EmailSender>,
+    pub email_templates: EmailTemplates,
+    pub test_user: TestUser,
+    /// Keeps cookies across requests and does not follow redirects.
+    pub api_client: reqwest::Client,
+    /// Stops the application, as SIGTERM does in production.
+    pub shutdown: ShutdownController,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = hash_password(&self.password);
+
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.as_bytes(), &salt)
+    .unwrap()
+    .to_string()
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.email_templates,
+                &self.address,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Sends the confirmation emails queued by `/subscriptions` and
+    /// `/subscriptions/resend`, as the background worker would.
+    pub async fn dispatch_pending_confirmation_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_send_confirmation_email(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.email_templates,
+                &self.address,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Once the request succeeds, also sends the confirmation email it queued.
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
-        reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
-            .expect("Failed to execute request")
+            .expect("Failed to execute request");
+        if response.status().is_success() {
+            self.dispatch_pending_confirmation_emails().await;
+        }
+        response
     }
 
-    pub fn get_confirmation_links(
+    /// Once the request succeeds, also sends the confirmation email it queued.
+    pub async fn post_subscription_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        if response.status().is_success() {
+            self.dispatch_pending_confirmation_emails().await;
+        }
+        response
+    }
+
+    /// Once the request succeeds, also sends the confirmation email it queued.
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions/resend", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request");
+        if response.status().is_success() {
+            self.dispatch_pending_confirmation_emails().await;
+        }
+        response
+    }
+
+    /// Recipients of every message sent through Postmark's batch endpoint.
+    pub async fn newsletter_recipients(&self) -> Vec<String> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == "/email/batch")
+            .flat_map(|request| {
+                let messages: Vec<serde_json::Value> =
+                    serde_json::from_slice(&request.body).unwrap();
+                messages
+                    .into_iter()
+                    .map(|message| message["To"].as_str().unwrap().to_owned())
+            })
+            .collect()
+    }
+
+    /// Publish with a fresh idempotency key.
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.post_newsletters_with_idempotency_key(body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
         &self,
-        email_request: &wiremock::Request
-    ) -> ConfirmationLinks {
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn login_as_test_user(&self) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
         let get_link = |s: &str| {
-            let links: Vec<_> = linkify::LinkFinder::new().links(s).filter(|l| *l.kind() == linkify::LinkKind::Url).collect();
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
 
             assert_eq!(links.len(), 1);
 
@@ -70,8 +326,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
@@ -88,12 +344,12 @@ impl Drop for TestApp {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async {
                 // 1. Conectar a la base de datos por defecto ('postgres') para poder borrar la otra
-                let mut options = sqlx::postgres::PgConnectOptions::new()
+                let options = sqlx::postgres::PgConnectOptions::new()
                     .host(&config.host)
                     .port(config.port)
                     .username(&config.username)
                     .password(config.password.expose_secret());
-                
+
                 let mut connection = PgConnection::connect_with(&options)
                     .await
                     .expect("Failed to connect to Postgres for cleanup");
@@ -110,7 +366,7 @@ impl Drop for TestApp {
                 // 3. Borrar la base de datos
                 let drop_query = format!(r#"DROP DATABASE "{}""#, db_name);
                 let _ = connection.execute(drop_query.as_str()).await;
-                
+
                 println!("Successfully dropped database: {}", db_name);
             });
         })
@@ -129,7 +385,11 @@ pub async fn spawn_app() -> TestApp {
         let mut c = get_configuration().expect("Failed to read configuration");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.email_client.backend = EmailBackend::Postmark;
         c.email_client.base_url = email_server.uri();
+        c.health.check_email_api = true;
+        // Keeps the tests of requests outliving the grace period short.
+        c.application.shutdown_grace_period_seconds = 1;
         c
     };
 
@@ -143,16 +403,50 @@ pub async fn spawn_app() -> TestApp {
 
     let address = format!("http://127.0.0.1:{}", application_port);
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    let shutdown = ShutdownController::new();
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(application.run_until_stopped(shutdown.subscribe()));
 
-    TestApp {
+    let test_app = TestApp {
         address,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         port: application_port,
         database_name: configuration.database.database_name.clone(),
-        db_configuration: configuration.database.clone()
-    }
+        db_configuration: configuration.database.clone(),
+        email_templates: configuration
+            .email_client
+            .templates(&configuration.application.default_locale)
+            .expect("Failed to load the email templates"),
+        email_client: configuration.email_client.client(),
+        test_user: TestUser::generate(),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
+        shutdown,
+    };
+
+    test_app.test_user.store(&test_app.db_pool).await;
+
+    test_app
+}
+
+/// A valid body for `POST /newsletters`.
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
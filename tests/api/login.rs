@@ -0,0 +1,81 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // The flash message is gone once it has been displayed
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let response = app.login_as_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}!", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn sessions_are_stored_in_postgres() {
+    let app = spawn_app().await;
+
+    app.login_as_test_user().await;
+
+    let stored = sqlx::query!("SELECT state FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch stored sessions.");
+    assert_eq!(stored.len(), 1);
+    assert_eq!(
+        stored[0].state["user_id"],
+        serde_json::json!(serde_json::to_string(&app.test_user.user_id).unwrap())
+    );
+}
+
+#[tokio::test]
+async fn logging_in_rotates_the_session_key() {
+    let app = spawn_app().await;
+
+    app.login_as_test_user().await;
+    let first_key = stored_session_keys(&app).await;
+    assert_eq!(first_key.len(), 1);
+
+    // Logging in again on top of an existing session must not reuse its key.
+    let response = app.login_as_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let second_key = stored_session_keys(&app).await;
+    assert_eq!(second_key.len(), 1);
+    assert_ne!(first_key, second_key);
+
+    let session_cookie = response
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie was set on login");
+    assert!(session_cookie.value().contains(&second_key[0]));
+}
+
+async fn stored_session_keys(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch stored sessions.")
+        .into_iter()
+        .map(|r| r.session_key)
+        .collect()
+}
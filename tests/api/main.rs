@@ -1,4 +1,16 @@
+mod admin_dashboard;
+mod authentication;
+mod change_password;
 mod health_check;
 mod helpers;
+mod login;
+mod metrics;
+mod newsletter;
+mod openapi;
+mod shutdown;
 mod subscription;
+mod subscription_cleanup;
 mod subscriptions_confirm;
+mod subscriptions_json;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
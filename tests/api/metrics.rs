@@ -0,0 +1,103 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn get_metrics(app: &TestApp) -> String {
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_and_status() {
+    let app = spawn_app().await;
+
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    app.post_subscription("name=Ursula".into()).await;
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#)
+    );
+    assert!(metrics
+        .contains(r#"http_requests_total{method="POST",route="/subscriptions",status="400"}"#));
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/health_check",status="200"}"#
+    ));
+}
+
+#[tokio::test]
+async fn metrics_count_requests_rejected_by_a_middleware() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/admin/dashboard",status="303"}"#));
+}
+
+#[tokio::test]
+async fn metrics_count_email_deliveries() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(r#"email_send_attempts_total{outcome="success"}"#));
+    assert!(metrics.contains(r#"email_send_duration_seconds_count{outcome="success"}"#));
+    assert!(metrics.contains("email_send_failures_total"));
+}
+
+#[tokio::test]
+async fn metrics_report_the_state_of_the_connection_pool() {
+    let app = spawn_app().await;
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(r#"db_pool_connections{pool="api",state="idle"}"#));
+    assert!(metrics.contains(r#"db_pool_connections{pool="api",state="in_use"}"#));
+    assert!(metrics.contains(r#"db_pool_max_connections{pool="api"}"#));
+}
+
+#[tokio::test]
+async fn metrics_time_the_wait_for_a_database_connection() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains(r#"db_pool_acquire_wait_seconds_count{pool="api"}"#));
+}
@@ -0,0 +1,101 @@
+use crate::helpers::spawn_app;
+
+const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+/// Client generators work from the `openapi.json` checked into the repository:
+/// it has to stay in sync with the document the application serves.
+/// Run with `UPDATE_OPENAPI_SNAPSHOT=1` to refresh it after changing the API.
+#[tokio::test]
+async fn the_served_openapi_document_matches_the_checked_in_snapshot() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/openapi.json", &app.address))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let served: serde_json::Value = response.json().await.unwrap();
+
+    if std::env::var("UPDATE_OPENAPI_SNAPSHOT").is_ok() {
+        let pretty = serde_json::to_string_pretty(&served).unwrap();
+        std::fs::write(SNAPSHOT, pretty + "\n").unwrap();
+    }
+
+    let snapshot: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(SNAPSHOT).expect("Failed to read the OpenAPI snapshot."),
+    )
+    .unwrap();
+    assert!(
+        served == snapshot,
+        "The served OpenAPI document differs from `openapi.json`. \
+        Run the tests with `UPDATE_OPENAPI_SNAPSHOT=1` and commit the result."
+    );
+}
+
+#[tokio::test]
+async fn the_openapi_document_describes_the_public_endpoints() {
+    let app = spawn_app().await;
+
+    let spec: serde_json::Value = reqwest::get(format!("{}/openapi.json", &app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    for path in [
+        "/health_check",
+        "/health/live",
+        "/health/ready",
+        "/subscriptions",
+        "/subscriptions/confirm",
+        "/subscriptions/resend",
+        "/subscriptions/unsubscribe",
+        "/newsletters",
+    ] {
+        assert!(
+            spec["paths"][path].is_object(),
+            "{} is not documented.",
+            path
+        );
+    }
+    assert!(spec["components"]["schemas"]["FormData"].is_object());
+}
+
+/// The snapshot only proves the document is stable: the `path` of a
+/// `#[utoipa::path]` annotation is never checked against the routes
+/// registered in `startup::run`, which this test does by calling each one.
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    let app = spawn_app().await;
+
+    let spec: serde_json::Value = reqwest::get(format!("{}/openapi.json", &app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    for (path, operations) in spec["paths"].as_object().unwrap() {
+        for method in operations.as_object().unwrap().keys() {
+            let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+            // Invalid input is fine: the request only has to reach a handler.
+            let response = client
+                .request(method.clone(), format!("{}{}", &app.address, path))
+                .send()
+                .await
+                .expect("Failed to execute request.");
+
+            assert!(
+                ![404, 405].contains(&response.status().as_u16()),
+                "{} {} is documented but not routed: {}.",
+                method,
+                path,
+                response.status()
+            );
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use sqlx::{Connection, PgConnection};
+use wiremock::{
+    matchers::{method, path},
+    Mock, Respond, ResponseTemplate,
+};
+use zero2prod::{
+    configuration::{get_configuration, EmailBackend, Settings},
+    issue_delivery_worker::run_worker_until_stopped,
+    shutdown::{ShutdownController, SHUTDOWN_MARGIN},
+    subscription_cleanup_worker::run_cleanup_until_stopped,
+};
+
+use crate::helpers::{spawn_app, PostmarkBatchResponder, TestApp};
+use crate::newsletter::create_confirmed_subscriber;
+
+/// The configuration of `app`, for the background workers.
+fn worker_configuration(app: &TestApp) -> Settings {
+    let mut c = get_configuration().expect("Failed to read configuration");
+    c.database = app.db_configuration.clone();
+    c.email_client.backend = EmailBackend::Postmark;
+    c.email_client.base_url = app.email_server.uri();
+    c
+}
+
+/// Waits for the email API to receive a request on `endpoint`.
+async fn wait_for_email_request(app: &TestApp, endpoint: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.iter().any(|r| r.url.path() == endpoint) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "No request made to {}", endpoint);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+struct SlowBatchResponder;
+
+impl Respond for SlowBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        PostmarkBatchResponder
+            .respond(request)
+            .set_delay(Duration::from_millis(500))
+    }
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_after_a_shutdown_is_requested() {
+    let app = spawn_app().await;
+
+    // The readiness check waits for the email API to answer.
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let request = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send();
+    let (response, _) = tokio::join!(request, async {
+        // Stop while the email API is being probed.
+        wait_for_email_request(&app, "/").await;
+        app.shutdown.trigger();
+    });
+
+    let report: serde_json::Value = response
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["checks"]["email_api"]["status"], "up");
+}
+
+/// Connections opened to the database of `app`, by any client.
+async fn open_database_connections(app: &TestApp) -> i64 {
+    let mut connection = PgConnection::connect_with(&app.db_configuration.without_db())
+        .await
+        .unwrap();
+    sqlx::query!(
+        "SELECT COUNT(*) AS count FROM pg_stat_activity WHERE datname = $1",
+        app.database_name
+    )
+    .fetch_one(&mut connection)
+    .await
+    .unwrap()
+    .count
+    .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn the_pool_is_closed_when_a_request_outlives_the_grace_period() {
+    let app = spawn_app().await;
+    let grace_period = Duration::from_secs(1);
+
+    // The probe of the email API times out after the grace period.
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_delay(grace_period * 10))
+        .mount(&app.email_server)
+        .await;
+
+    // Dropped by the API once the grace period is over.
+    let request = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send();
+    let (_, deadline) = tokio::join!(request, async {
+        wait_for_email_request(&app, "/").await;
+        app.shutdown.trigger();
+        // What `main` gives the tasks before exiting.
+        Instant::now() + grace_period + SHUTDOWN_MARGIN
+    });
+
+    // Only the connections of the API are left.
+    app.db_pool.close().await;
+    while open_database_connections(&app).await > 0 {
+        assert!(
+            Instant::now() < deadline + Duration::from_secs(10),
+            "The API never closed its pool"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(
+        Instant::now() < deadline,
+        "The API closed its pool after the process would have exited"
+    );
+}
+
+#[tokio::test]
+async fn no_new_connections_are_accepted_after_a_shutdown() {
+    let app = spawn_app().await;
+
+    app.shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let outcome = reqwest::Client::builder()
+        .timeout(Duration::from_secs(1))
+        .build()
+        .unwrap()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await;
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn the_delivery_worker_finishes_the_batch_in_progress_before_stopping() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(SlowBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let shutdown = ShutdownController::new();
+    let worker = tokio::spawn(run_worker_until_stopped(
+        worker_configuration(&app),
+        shutdown.subscribe(),
+    ));
+    wait_for_email_request(&app, "/email/batch").await;
+    shutdown.trigger();
+
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[tokio::test]
+async fn the_cleanup_worker_stops_without_waiting_for_its_next_tick() {
+    let app = spawn_app().await;
+
+    let shutdown = ShutdownController::new();
+    let worker = tokio::spawn(run_cleanup_until_stopped(
+        worker_configuration(&app),
+        shutdown.subscribe(),
+    ));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    shutdown.trigger();
+
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+}
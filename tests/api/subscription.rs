@@ -1,4 +1,3 @@
-use actix_web::dev::Url;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -21,7 +20,7 @@ async fn subcribe_return_a_400_when_fields_are_present_but_invalid() {
 
     for (body, description) in test_cases {
         let response = client
-            .post(&format!("{}/subscriptions", &app.address))
+            .post(format!("{}/subscriptions", &app.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -51,7 +50,7 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 
     for (invalid_body, error_message) in test_cases {
         let response = client
-            .post(&format!("{}/subscriptions", &app.address))
+            .post(format!("{}/subscriptions", &app.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(invalid_body)
             .send()
@@ -182,8 +181,8 @@ async fn subscribe_send_a_confirmation_email_for_valid_data() {
         links[0].as_str().to_owned()
     };
 
-    let html_link = get_link(&body["HtmlBody"].as_str().unwrap());
-    let text_link = get_link(&body["TextBody"].as_str().unwrap());
+    let html_link = get_link(body["HtmlBody"].as_str().unwrap());
+    let text_link = get_link(body["TextBody"].as_str().unwrap());
 
     assert_eq!(html_link, text_link);
 }
@@ -194,13 +193,438 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
-    Mock::given(path("/email")).and(method("POST")).respond_with(ResponseTemplate::new(200)).mount(&app.email_server).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
 
     app.post_subscription(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
 
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn subscribe_greets_the_subscriber_by_name_in_the_confirmation_email() {
+    let app = spawn_app().await;
+
+    let body = "name=ursula%20%26%20le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert_eq!(body["Subject"], "Welcome!");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hi ursula &amp; le guin,"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hi ursula & le guin,"));
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_different_token_for_each_subscriber() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscription("name=tolkien&email=jrr_tolkien%40gmail.com".into())
+        .await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+
+    assert_ne!(first_link, second_link);
+
+    let stored_tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch stored tokens");
+
+    assert_eq!(stored_tokens.len(), 2);
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_there_is_a_fatal_database_error() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN email;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+}
+
+#[tokio::test]
+async fn subscribe_does_not_persist_the_subscriber_if_the_token_cannot_be_stored() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token CASCADE;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to query subscriptions");
+
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_returns_the_validation_error_in_the_response_body() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=Ursula&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("definitely-not-an-email is not valid subscriber email"));
+}
+
+#[tokio::test]
+async fn subscribe_responds_before_the_confirmation_email_is_sent() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .error_for_status()
+        .unwrap();
+
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(1));
+}
+
+#[tokio::test]
+async fn a_confirmation_email_that_cannot_be_sent_is_retried_later() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!("SELECT n_retries, execute_after FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The confirmation email is no longer queued.");
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.execute_after > chrono::Utc::now());
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_same_confirmation_link() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first_response = app.post_subscription(body.into()).await;
+    // Out of the cooldown window.
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let second_response = app.post_subscription(body.into()).await;
+    // Sending the link again restarts the cooldown: no third email.
+    app.post_subscription(body.into()).await;
+
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+
+    assert_eq!(first_links.html, second_links.html);
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_twice_in_a_row_sends_a_single_confirmation_email() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let first_response = app.post_subscription(body.into()).await;
+    let second_response = app.post_subscription(body.into()).await;
+
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn concurrent_first_subscriptions_of_an_address_are_handled_gracefully() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (first_response, second_response) = tokio::join!(
+        app.post_subscription(body.into()),
+        app.post_subscription(body.into())
+    );
+
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(1));
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_returns_200_without_sending_an_email() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.content_length(), Some(0));
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_the_token_expired_sends_a_new_link() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    sqlx::query!(
+        r#"
+            UPDATE subscription_tokens
+            SET
+                created_at = now() - interval '2 days',
+                expires_at = now() - interval '1 minute'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.post_subscription(body.into()).await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+
+    assert_ne!(first_links.html, second_links.html);
+}
+
+#[tokio::test]
+async fn subscribe_sends_the_confirmation_email_in_the_locale_picked_on_the_form() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=es";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "¡Bienvenido!");
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.locale.as_deref(), Some("es"));
+}
+
+#[tokio::test]
+async fn subscribe_falls_back_on_the_accept_language_header() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", "fr-FR, es-MX;q=0.8, en;q=0.5")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .error_for_status()
+        .unwrap();
+    app.dispatch_pending_confirmation_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "¡Bienvenido!");
+}
+
+#[tokio::test]
+async fn subscribe_uses_the_default_locale_for_unknown_languages() {
+    let app = spawn_app().await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=fr";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Welcome!");
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.locale.as_deref(), Some("en"));
+}
@@ -0,0 +1,119 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::subscription_cleanup_worker::delete_stale_pending_subscribers;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp, name: &str, email: &str) {
+    let body = serde_urlencoded::to_string([("name", name), ("email", email)]).unwrap();
+    app.post_subscription(body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_deleted_with_their_tokens() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app, "stale", "stale@example.com").await;
+    subscribe(&app, "fresh", "fresh@example.com").await;
+    sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET subscribed_at = now() - interval '30 days'
+            WHERE email = 'stale@example.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+            UPDATE subscription_tokens
+            SET expires_at = now() - interval '29 days'
+            WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = 'stale@example.com')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, chrono::Duration::days(7))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 1);
+    let remaining = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, "fresh@example.com");
+    let tokens = sqlx::query!("SELECT COUNT(*) AS count FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, Some(1));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_never_deleted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET status = 'confirmed', subscribed_at = now() - interval '30 days'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '29 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, chrono::Duration::days(7))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 0);
+}
+
+#[tokio::test]
+async fn a_recently_resent_link_keeps_an_old_pending_subscriber() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    // Subscribed long ago, but the current link is still valid.
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, chrono::Duration::days(7))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 0);
+}
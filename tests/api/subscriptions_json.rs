@@ -0,0 +1,128 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn subscribe_json_persists_the_subscriber_and_sends_a_confirmation_email() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!({ "status": "ok" }));
+
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_json_reports_every_invalid_field() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "",
+            "email": "definitely-not-an-email"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let errors = body["errors"].as_object().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert!(errors["name"]
+        .as_str()
+        .unwrap()
+        .contains("is not a valid subscriber name"));
+    assert!(errors["email"]
+        .as_str()
+        .unwrap()
+        .contains("definitely-not-an-email is not valid subscriber email"));
+}
+
+#[tokio::test]
+async fn subscribe_json_only_reports_the_invalid_fields() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "definitely-not-an-email"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let errors = body["errors"].as_object().unwrap();
+    assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["email"]);
+}
+
+#[tokio::test]
+async fn subscribe_json_returns_a_json_error_for_a_malformed_body() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "le guin" }),
+            "missing the email",
+        ),
+        (
+            serde_json::json!({ "email": "ursula_le_guin@gmail.com" }),
+            "missing the name",
+        ),
+        (serde_json::json!([]), "not an object"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_subscription_json(&body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            body["errors"]["body"].is_string(),
+            "The API did not return a JSON error when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn form_submissions_still_get_plain_text_errors() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=Ursula&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "text/plain; charset=utf-8"
+    );
+}
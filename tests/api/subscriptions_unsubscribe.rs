@@ -0,0 +1,276 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::{
+    helpers::{newsletter_request_body, spawn_app, PostmarkBatchResponder, TestApp},
+    newsletter::{create_confirmed_subscriber, create_unconfirmed_subscriber},
+};
+
+async fn stored_subscriber(app: &TestApp) -> (String, String) {
+    let saved = sqlx::query!("SELECT unsubscribe_token, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    (saved.unsubscribe_token, saved.status)
+}
+
+fn unsubscribe_url(app: &TestApp, unsubscribe_token: &str) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        app.address, unsubscribe_token
+    )
+}
+
+async fn post_one_click_unsubscribe(url: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn unsubscribing_without_token_returns_400() {
+    let app = spawn_app().await;
+    let url = format!("{}/subscriptions/unsubscribe", app.address);
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = post_one_click_unsubscribe(&url).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribing_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+    let url = unsubscribe_url(&app, "unknown-token");
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = post_one_click_unsubscribe(&url).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_landing_page_does_not_unsubscribe_by_itself() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+
+    let response = reqwest::get(unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+    assert!(html_page.contains(&unsubscribe_token));
+
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+
+    let response = post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_address() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+    post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_a_pending_address_invalidates_its_confirmation_link() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+    post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_unsubscribed_address_can_subscribe_again() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let first_links = app.get_confirmation_links(email_request);
+    reqwest::get(first_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+    post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let second_links = app.get_confirmation_links(email_request);
+    reqwest::get(second_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_working_one_click_unsubscribe_header() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|request| request.url.path() == "/email/batch")
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let headers = messages[0]["Headers"].as_array().unwrap();
+    let header_value = |name: &str| {
+        headers
+            .iter()
+            .find(|header| header["Name"] == name)
+            .and_then(|header| header["Value"].as_str())
+            .unwrap()
+            .to_owned()
+    };
+
+    assert_eq!(
+        header_value("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+    let list_unsubscribe = header_value("List-Unsubscribe");
+    let url = list_unsubscribe
+        .strip_prefix('<')
+        .and_then(|url| url.strip_suffix('>'))
+        .unwrap();
+
+    let response = post_one_click_unsubscribe(url).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let (_, status) = stored_subscriber(&app).await;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+    post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribing_drops_deliveries_that_are_still_queued() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (unsubscribe_token, _) = stored_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    post_one_click_unsubscribe(&unsubscribe_url(&app, &unsubscribe_token))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}